@@ -18,44 +18,234 @@
 //! ```
 
 use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::prelude::*;
 use std::io::BufWriter;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 /// Supported file types with TestDir
-#[derive(PartialEq, Debug)]
 pub enum FileType {
     /// Create empty file
     EmptyFile,
-    /// Create a file with random content of the given size
-    RandomFile(usize),
-    /// Create a file with a given len of "0"s
-    ZeroFile(usize),
-    //ContentFile(&dyn std::io::Read),
+    /// Create a file with random content of the given size, a size of 0 creates an empty file
+    RandomFile(u64),
+    /// Create a file with a given len of "0"s. Note these are ASCII `'0'` characters (0x30), not null bytes,
+    /// see `NullFile` for those. A len of 0 creates an empty file.
+    ZeroFile(u64),
+    /// Create a file of the given size filled with null bytes (0x00), unlike the ASCII `'0'`s of `ZeroFile`.
+    /// A size of 0 creates an empty file.
+    NullFile(u64),
+    /// Create a file of `size` bytes filled with repetitions of `pattern`, the last one truncated
+    PatternFile { pattern: Vec<u8>, size: u64 },
+    /// Create a file with `block` written `count` times
+    RepeatedFile { block: Vec<u8>, count: usize },
+    /// Create a file with the given content
+    ContentFile(Vec<u8>),
+    /// Create a file with the given text encoded as UTF-8
+    Utf8File(String),
+    /// Create a file with the given content, then truncate or zero-extend it to `len` bytes
+    TruncatedFile { content: Vec<u8>, len: u64 },
+    /// Create a file with the chunks yielded by the iterator, written one after another
+    ContentFromIter(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+    /// Create a file with the pretty-printed JSON value
+    #[cfg(feature = "json")]
+    JsonFile(serde_json::Value),
+    /// Create a gzip file with the given uncompressed content
+    #[cfg(feature = "gzip")]
+    GzipFile(Vec<u8>),
+    /// Create a symlink with the given target stored verbatim. A relative target is resolved
+    /// against the directory of the link, e.g. `../b` for the link `a/link` points to `b`.
+    RelativeSymlink(PathBuf),
+    /// Create a dangling symlink to the given target under the root. The target must not exist
+    /// and must stay inside the root, the link stores it relative to the directory of the link.
+    BrokenSymlink(PathBuf),
+    /// Create a character device node. Needs privileges (e.g. root), otherwise creating it fails
+    /// with `PermissionDenied`.
+    #[cfg(all(unix, feature = "unix-special"))]
+    CharDevice { major: u32, minor: u32 },
+    /// Create a block device node. Needs privileges (e.g. root), otherwise creating it fails
+    /// with `PermissionDenied`.
+    #[cfg(all(unix, feature = "unix-special"))]
+    BlockDevice { major: u32, minor: u32 },
     /// Create a directory
     Dir,
+    /// Create a directory and set its permissions to the given mode (Unix only).
+    /// Only the leaf directory gets the mode, missing parents use the default permissions.
+    /// The directory is made writable again before it is removed on drop.
+    DirWithMode(u32),
+}
+
+// An iterator cannot be compared, `ContentFromIter` is never equal to anything
+impl PartialEq for FileType {
+    fn eq(&self, other: &Self) -> bool {
+        use FileType::*;
+        match (self, other) {
+            (EmptyFile, EmptyFile) => true,
+            (RandomFile(a), RandomFile(b)) => a == b,
+            (ZeroFile(a), ZeroFile(b)) => a == b,
+            (NullFile(a), NullFile(b)) => a == b,
+            (
+                PatternFile { pattern, size },
+                PatternFile {
+                    pattern: other_pattern,
+                    size: other_size,
+                },
+            ) => pattern == other_pattern && size == other_size,
+            (
+                RepeatedFile { block, count },
+                RepeatedFile {
+                    block: other_block,
+                    count: other_count,
+                },
+            ) => block == other_block && count == other_count,
+            (ContentFile(a), ContentFile(b)) => a == b,
+            (Utf8File(a), Utf8File(b)) => a == b,
+            (
+                TruncatedFile { content, len },
+                TruncatedFile {
+                    content: other_content,
+                    len: other_len,
+                },
+            ) => content == other_content && len == other_len,
+            (ContentFromIter(_), ContentFromIter(_)) => false,
+            #[cfg(feature = "json")]
+            (JsonFile(a), JsonFile(b)) => a == b,
+            #[cfg(feature = "gzip")]
+            (GzipFile(a), GzipFile(b)) => a == b,
+            (RelativeSymlink(a), RelativeSymlink(b)) => a == b,
+            (BrokenSymlink(a), BrokenSymlink(b)) => a == b,
+            #[cfg(all(unix, feature = "unix-special"))]
+            (
+                CharDevice { major, minor },
+                CharDevice {
+                    major: other_major,
+                    minor: other_minor,
+                },
+            ) => major == other_major && minor == other_minor,
+            #[cfg(all(unix, feature = "unix-special"))]
+            (
+                BlockDevice { major, minor },
+                BlockDevice {
+                    major: other_major,
+                    minor: other_minor,
+                },
+            ) => major == other_major && minor == other_minor,
+            (Dir, Dir) => true,
+            (DirWithMode(a), DirWithMode(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use FileType::*;
+        match self {
+            EmptyFile => f.write_str("EmptyFile"),
+            RandomFile(size) => f.debug_tuple("RandomFile").field(size).finish(),
+            ZeroFile(size) => f.debug_tuple("ZeroFile").field(size).finish(),
+            NullFile(size) => f.debug_tuple("NullFile").field(size).finish(),
+            PatternFile { pattern, size } => f
+                .debug_struct("PatternFile")
+                .field("pattern", pattern)
+                .field("size", size)
+                .finish(),
+            RepeatedFile { block, count } => f
+                .debug_struct("RepeatedFile")
+                .field("block", block)
+                .field("count", count)
+                .finish(),
+            ContentFile(content) => f.debug_tuple("ContentFile").field(content).finish(),
+            Utf8File(text) => f.debug_tuple("Utf8File").field(text).finish(),
+            TruncatedFile { content, len } => f
+                .debug_struct("TruncatedFile")
+                .field("content", content)
+                .field("len", len)
+                .finish(),
+            ContentFromIter(_) => f.write_str("ContentFromIter(..)"),
+            #[cfg(feature = "json")]
+            JsonFile(value) => f.debug_tuple("JsonFile").field(value).finish(),
+            #[cfg(feature = "gzip")]
+            GzipFile(content) => f.debug_tuple("GzipFile").field(content).finish(),
+            RelativeSymlink(target) => f.debug_tuple("RelativeSymlink").field(target).finish(),
+            BrokenSymlink(target) => f.debug_tuple("BrokenSymlink").field(target).finish(),
+            #[cfg(all(unix, feature = "unix-special"))]
+            CharDevice { major, minor } => f
+                .debug_struct("CharDevice")
+                .field("major", major)
+                .field("minor", minor)
+                .finish(),
+            #[cfg(all(unix, feature = "unix-special"))]
+            BlockDevice { major, minor } => f
+                .debug_struct("BlockDevice")
+                .field("major", major)
+                .field("minor", minor)
+                .finish(),
+            Dir => f.write_str("Dir"),
+            DirWithMode(mode) => f.debug_tuple("DirWithMode").field(mode).finish(),
+        }
+    }
+}
+
+/// Operation performed on a `TestDir`, recorded with the `operation-log` feature once it succeeded.
+/// All paths are relative to the root and normalized, e.g. `a/../f` is recorded as `f`.
+#[cfg(feature = "operation-log")]
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operation {
+    /// `create` or `try_create`, `filetype` is the debug format of the `FileType`
+    Create { path: PathBuf, filetype: String },
+    /// `create_handle`
+    CreateHandle { path: PathBuf },
+    /// `create_with_options`
+    CreateWithOptions { path: PathBuf },
+    /// `create_from_file` copying the file `src`
+    CreateFromFile { path: PathBuf, src: PathBuf },
+    /// `remove`, `try_remove` or an entry removed by `remove_matching`
+    Remove { path: PathBuf },
+    /// `merge` with a `TestDir` rooted in `from`
+    Merge { from: PathBuf },
+    /// `touch`
+    Touch { path: PathBuf },
+    /// `concat_into` joining the files `sources` into `path`
+    Concat {
+        path: PathBuf,
+        sources: Vec<PathBuf>,
+    },
+    /// `rename_root` to the sibling `name`
+    RenameRoot { name: String },
 }
 
 /// Temporary directory
 pub struct TempDir {
     path: PathBuf,
-    delete: PathBuf,
+    // None if the directory existed before, it is never removed then
+    delete: Option<PathBuf>,
+    warn_on_cleanup: bool,
+    // Make the directory tree writable again before removing it
+    restore_permissions: bool,
+    // Shared list and the deletion root recorded in it on drop, cloned up front so drop
+    // does not allocate the entry
+    cleanup_log: Option<(Arc<Mutex<Vec<PathBuf>>>, PathBuf)>,
+    // Keep the directory if dropped during a panic
+    leak_on_panic: bool,
 }
 
 impl TempDir {
     /// Try to create a temporary directory inside system tmp directory.
     pub fn temp() -> std::io::Result<Self> {
         let mut temp = std::env::temp_dir().to_path_buf();
-        temp.push(TempDir::random_name());
+        temp.push(TempDir::random_name(DEFAULT_ENTROPY));
         TempDir::create(temp.as_path())
     }
 
     /// Try to create a temporary directory inside the current directory.
     pub fn current_rnd() -> std::io::Result<Self> {
         let mut temp = std::env::current_dir()?.to_path_buf();
-        temp.push(TempDir::random_name());
+        temp.push(TempDir::random_name(DEFAULT_ENTROPY));
         TempDir::create(temp.as_path())
     }
 
@@ -71,28 +261,76 @@ impl TempDir {
         self.path.clone()
     }
 
+    /// Get the path removed on drop. It is the topmost directory created together with the
+    /// temporary directory, e.g. `a` for a newly created `a/b/c`.
+    /// `None` if the directory already existed, an existing directory is never removed.
+    pub fn deletion_root(&self) -> Option<&Path> {
+        self.delete.as_deref()
+    }
+
+    /// Remove only the temporary directory itself on drop, keep the created parent directories.
+    pub fn keep_parents(&mut self) {
+        if self.delete.is_some() {
+            self.set_delete(self.path.clone());
+        }
+    }
+
+    // Follow the directory moved to `path`, a deletion root above it is unaffected
+    fn moved_to(&mut self, path: &Path) {
+        if self.delete.as_ref() == Some(&self.path) {
+            self.set_delete(path.to_path_buf());
+        }
+        self.path = path.to_path_buf();
+    }
+
+    // Record the deletion root into `sink` on drop
+    fn log_cleanup(&mut self, sink: Arc<Mutex<Vec<PathBuf>>>) {
+        if let Some(delete) = self.delete.as_ref() {
+            self.cleanup_log = Some((sink, delete.clone()));
+        }
+    }
+
+    // Change the deletion root, keeping the entry of the cleanup log in sync
+    fn set_delete(&mut self, delete: PathBuf) {
+        if let Some((_, entry)) = self.cleanup_log.as_mut() {
+            *entry = delete.clone();
+        }
+        self.delete = Some(delete);
+    }
+
     // Helper functions
     fn create(path: &Path) -> std::io::Result<Self> {
-        let mut p = path;
-        while let Some(ppath) = p.parent() {
-            if ppath.exists() {
-                break;
+        // Topmost directory which does not exist yet
+        let delete = if path.exists() {
+            None
+        } else {
+            let mut p = path;
+            while let Some(ppath) = p.parent() {
+                // An empty parent of a relative path is the current directory
+                if ppath.as_os_str().is_empty() || ppath.exists() {
+                    break;
+                }
+                p = ppath;
             }
-            p = ppath;
-        }
-        fs::create_dir_all(&path)?;
+            Some(p.to_path_buf())
+        };
+        fs::create_dir_all(path)?;
         Ok(TempDir {
             path: path.to_path_buf(),
-            delete: p.to_path_buf(),
+            delete,
+            warn_on_cleanup: false,
+            restore_permissions: false,
+            cleanup_log: None,
+            leak_on_panic: false,
         })
     }
 
-    fn random_name() -> String {
+    fn random_name(len: usize) -> String {
         // https://stackoverflow.com/a/65478580/113632
         thread_rng()
             .sample_iter(&Alphanumeric)
             .map(char::from)
-            .take(8)
+            .take(len)
             .collect()
     }
 }
@@ -100,19 +338,418 @@ impl TempDir {
 impl Drop for TempDir {
     /// Delete the created directory tree.
     fn drop(&mut self) {
-        let _ = fs::remove_dir_all(self.delete.as_path());
+        let delete = match self.delete.as_ref() {
+            Some(delete) => delete,
+            None => return,
+        };
+        if self.leak_on_panic && std::thread::panicking() {
+            eprintln!("Panicking, temp dir {:?} is preserved", self.path);
+            return;
+        }
+        #[cfg(unix)]
+        if self.restore_permissions {
+            let _ = restore_owner_access(self.path.as_path());
+        }
+        if let Some((log, entry)) = self.cleanup_log.as_mut() {
+            // Never panic in drop, a poisoned log is still usable
+            let mut log = log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            log.push(std::mem::take(entry));
+        }
+        let result = remove_tree(delete.as_path());
+        if let Err(err) = result {
+            if self.warn_on_cleanup {
+                eprintln!("Cannot remove temp dir {:?}: {}", delete, err);
+            }
+        }
+    }
+}
+
+/// Number of attempts to remove a directory tree on Windows
+#[cfg(windows)]
+const REMOVE_ATTEMPTS: u32 = 5;
+
+// Antivirus or indexer may briefly hold a handle on Windows, retry with backoff
+#[cfg(windows)]
+fn remove_tree(path: &Path) -> std::io::Result<()> {
+    let mut delay = std::time::Duration::from_millis(10);
+    let mut attempt = 1;
+    loop {
+        match fs::remove_dir_all(path) {
+            Err(_) if attempt < REMOVE_ATTEMPTS && path.exists() => {
+                let _ = clear_readonly(path);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn remove_tree(path: &Path) -> std::io::Result<()> {
+    fs::remove_dir_all(path)
+}
+
+// Only compiled on Windows, where it does not make the file world writable
+#[cfg(windows)]
+#[allow(clippy::permissions_set_readonly_false)]
+fn clear_readonly(path: &Path) -> std::io::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let _ = clear_readonly(entry?.path().as_path());
+        }
+    }
+    Ok(())
+}
+
+// Give the owner full access to every directory of the tree, entries of a directory
+// without write permission cannot be removed
+#[cfg(unix)]
+fn restore_owner_access(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        let mode = metadata.permissions().mode();
+        if mode & 0o700 != 0o700 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o700))?;
+        }
+        for entry in fs::read_dir(path)? {
+            let _ = restore_owner_access(entry?.path().as_path());
+        }
+    }
+    Ok(())
+}
+
+/// Differences between a `TestDir` and an expected directory, see `TestDir::diff_against`.
+/// All paths are relative to the compared roots and sorted.
+#[derive(PartialEq, Debug, Default)]
+pub struct DirDiff {
+    /// Entries existing only in the `TestDir`
+    pub only_in_actual: Vec<PathBuf>,
+    /// Entries existing only in the expected directory
+    pub only_in_expected: Vec<PathBuf>,
+    /// Files with different content, symlinks with different targets, or entries of different types
+    /// on each side, e.g. a file and a directory
+    pub content_differs: Vec<PathBuf>,
+}
+
+impl DirDiff {
+    /// Returns true if both directories are the same
+    pub fn is_empty(&self) -> bool {
+        self.only_in_actual.is_empty()
+            && self.only_in_expected.is_empty()
+            && self.content_differs.is_empty()
+    }
+}
+
+// Collect all entries under `root` as relative path -> file type, symlinks are not followed
+fn collect_entries(
+    root: &Path,
+    entries: &mut BTreeMap<PathBuf, fs::FileType>,
+) -> std::io::Result<()> {
+    walk_dir(root, false, &mut |path, metadata| {
+        let relative = path.strip_prefix(root).unwrap().to_path_buf();
+        entries.insert(relative, metadata.file_type());
+    })
+}
+
+// Visit every entry under `dir` recursively, the entries of a directory in sorted order
+fn walk_dir(
+    dir: &Path,
+    follow_symlinks: bool,
+    f: &mut dyn FnMut(&Path, &fs::Metadata),
+) -> std::io::Result<()> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    paths.sort();
+    for path in paths {
+        let metadata = if follow_symlinks {
+            // A broken symlink has nothing to follow
+            path.metadata().or_else(|_| path.symlink_metadata())?
+        } else {
+            path.symlink_metadata()?
+        };
+        f(path.as_path(), &metadata);
+        if metadata.is_dir() {
+            walk_dir(path.as_path(), follow_symlinks, f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Size of the buffer used to write generated content
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Size of the buffer filled by the OS random generator at once for unseeded random files
+const RANDOM_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+// Write `size` bytes in chunks of `chunk_size`, `fill` generates the content of each chunk
+fn write_chunks<W: Write>(
+    writer: &mut W,
+    size: u64,
+    chunk_size: u64,
+    mut fill: impl FnMut(&mut [u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut chunk = vec![0; size.min(chunk_size) as usize];
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = remaining.min(chunk_size) as usize;
+        fill(&mut chunk[..len])?;
+        writer.write_all(&chunk[..len])?;
+        remaining -= len as u64;
+    }
+    Ok(())
+}
+
+// Write `size` bytes of `pattern` repeated, the pattern is tiled into chunks to write
+// a short pattern in few large writes
+fn write_pattern<W: Write>(writer: &mut W, pattern: &[u8], size: u64) -> std::io::Result<()> {
+    if pattern.is_empty() {
+        return Ok(());
+    }
+    let len = pattern.len();
+    let tiled: Vec<u8> = pattern
+        .iter()
+        .copied()
+        .cycle()
+        .take(size.min(CHUNK_SIZE) as usize + len)
+        .collect();
+    let mut offset = 0;
+    write_chunks(writer, size, CHUNK_SIZE, |chunk| {
+        let start = offset % len;
+        chunk.copy_from_slice(&tiled[start..start + chunk.len()]);
+        offset += chunk.len();
+        Ok(())
+    })
+}
+
+fn relative_path_error() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "Only relative paths are allowed.",
+    )
+}
+
+// Resolve `.` and `..` lexically, None if the path is absolute or escapes the root
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(name) => normalized.push(name),
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+// Normalized relative path of an entry inside the root, never the root itself
+fn checked_path<P: AsRef<Path>>(path: P) -> std::io::Result<PathBuf> {
+    let path = path.as_ref();
+    if path.is_absolute() {
+        return Err(relative_path_error());
+    }
+    match normalize(path) {
+        Some(normalized) if normalized.as_os_str().is_empty() => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Path {:?} refers to the root", path),
+        )),
+        Some(normalized) => Ok(normalized),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Path {:?} escapes the root", path),
+        )),
+    }
+}
+
+// Target of the link `path` pointing to `target`, both normalized paths relative to the root
+fn relative_link(path: &Path, target: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let common = dir
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut link = PathBuf::new();
+    for _ in dir.components().skip(common) {
+        link.push("..");
+    }
+    link.extend(target.components().skip(common));
+    link
+}
+
+/// Number of bytes of the actual content shown in assertion messages
+const SNIPPET_LEN: usize = 64;
+
+fn snippet(content: &[u8]) -> String {
+    let end = content.len().min(SNIPPET_LEN);
+    let mut snippet = String::from_utf8_lossy(&content[..end]).into_owned();
+    if content.len() > end {
+        snippet.push_str("...");
     }
+    snippet
 }
 
-/// Test directory creator 
+/// Number of random characters in a generated directory name
+const DEFAULT_ENTROPY: usize = 8;
+
+/// Test directory creator
+///
+/// `TestDir` is `Send` and `Sync`. The read-only API (`root`, `path`, ...) can be used from many threads
+/// at once, to modify a shared `TestDir` wrap it in a `Mutex` and use `try_create`/`try_remove`.
 pub struct TestDir {
     // Directory lifetime
-    _tempdir: Option<TempDir>,
+    tempdir: Option<TempDir>,
 
     root: PathBuf,
 
     files: Vec<PathBuf>,
     dirs: Vec<PathBuf>,
+
+    // Seeded generator for random content, thread_rng if not set
+    rng: Option<StdRng>,
+    // Flush and fsync every created file
+    sync_writes: bool,
+    // Modes applied to every created file and directory
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    // Create missing parent directories of created entries
+    ensure_parent: bool,
+
+    #[cfg(feature = "operation-log")]
+    operations: Vec<Operation>,
+}
+
+/// Configuration of a `TestDir` before it is created
+///
+/// ```
+/// use test_dir::{TestDir,FileType,DirBuilder};
+///
+/// let temp = TestDir::builder()
+///     .prefix("fixture_")
+///     .seed(42)
+///     .build()
+///     .unwrap()
+///     .create("random_file", FileType::RandomFile(100));
+/// ```
+pub struct TestDirBuilder {
+    base: Base,
+    name: Option<PathBuf>,
+    prefix: String,
+    entropy: usize,
+    seed: Option<u64>,
+    warn_on_cleanup: bool,
+    capacity: (usize, usize),
+}
+
+// Directory the TestDir is created in
+enum Base {
+    Temp,
+    Current,
+    Dir(PathBuf),
+}
+
+impl TestDirBuilder {
+    /// Create the directory inside the current directory instead of the system temp directory
+    pub fn in_current_dir(mut self) -> Self {
+        self.base = Base::Current;
+        self
+    }
+
+    /// Create the directory inside `base` instead of the system temp directory
+    pub fn in_dir(mut self, base: &Path) -> Self {
+        self.base = Base::Dir(base.to_path_buf());
+        self
+    }
+
+    /// Use the given relative `path` instead of a random name
+    pub fn name(mut self, path: &str) -> Self {
+        self.name = Some(PathBuf::from(path));
+        self
+    }
+
+    /// Prefix the random directory name with `prefix`
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Number of random characters in the directory name
+    pub fn entropy(mut self, len: usize) -> Self {
+        self.entropy = len;
+        self
+    }
+
+    /// Seed the generator used for random file content, so the content is reproducible
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Print a warning to stderr if the directory cannot be removed on drop
+    pub fn warn_on_cleanup(mut self) -> Self {
+        self.warn_on_cleanup = true;
+        self
+    }
+
+    /// Reserve room to track `files` files and `dirs` directories, avoids reallocations
+    /// when generating huge fixtures
+    pub fn capacity(mut self, files: usize, dirs: usize) -> Self {
+        self.capacity = (files, dirs);
+        self
+    }
+
+    /// Create the directory
+    pub fn build(self) -> std::io::Result<TestDir> {
+        let mut path = match self.base {
+            Base::Temp => std::env::temp_dir(),
+            Base::Current => std::env::current_dir()?,
+            Base::Dir(base) => base,
+        };
+        match self.name {
+            Some(name) => path.push(name),
+            None => path.push(format!(
+                "{}{}",
+                self.prefix,
+                TempDir::random_name(self.entropy)
+            )),
+        }
+        let mut tempdir = TempDir::create(path.as_path())?;
+        tempdir.warn_on_cleanup = self.warn_on_cleanup;
+
+        let mut dir = TestDir::new(tempdir);
+        dir.rng = self.seed.map(StdRng::seed_from_u64);
+        dir.files.reserve(self.capacity.0);
+        dir.dirs.reserve(self.capacity.1);
+        Ok(dir)
+    }
+}
+
+impl Default for TestDirBuilder {
+    fn default() -> Self {
+        Self {
+            base: Base::Temp,
+            name: None,
+            prefix: String::new(),
+            entropy: DEFAULT_ENTROPY,
+            seed: None,
+            warn_on_cleanup: false,
+            capacity: (0, 0),
+        }
+    }
 }
 
 /// File structure builder trait
@@ -122,16 +759,33 @@ pub trait DirBuilder {
     /// Remove a file or directory under the `path`
     fn remove(self, path: &str) -> Self;
     /// Prefix `path` with the current context of the DirBuilder
+    ///
+    /// Panics if the path escapes the root
     fn path(&self, path: &str) -> PathBuf;
     /// Return the root path to the temporary directory
     fn root(&self) -> &Path;
 }
 
 impl TestDir {
+    /// Returns a builder to configure the temporary directory before creating it
+    pub fn builder() -> TestDirBuilder {
+        TestDirBuilder::default()
+    }
+
     /// Creates if possible a temporary directory
     pub fn temp() -> Self {
-        if let Ok(tempdir) = TempDir::temp() {
-            TestDir::new(tempdir)
+        if let Ok(dir) = TestDir::builder().build() {
+            dir
+        } else {
+            panic!("Cannot create temp dir in system temp");
+        }
+    }
+
+    /// Creates if possible a temporary directory tracking up to `files` files and `dirs`
+    /// directories without reallocating
+    pub fn temp_with_capacity(files: usize, dirs: usize) -> Self {
+        if let Ok(dir) = TestDir::builder().capacity(files, dirs).build() {
+            dir
         } else {
             panic!("Cannot create temp dir in system temp");
         }
@@ -139,8 +793,8 @@ impl TestDir {
 
     /// Creates if possible a temporary directory with random name inside the current directory
     pub fn current_rnd() -> Self {
-        if let Ok(tempdir) = TempDir::current_rnd() {
-            TestDir::new(tempdir)
+        if let Ok(dir) = TestDir::builder().in_current_dir().build() {
+            dir
         } else {
             panic!("Cannot create temp dir in current directory")
         }
@@ -148,148 +802,1102 @@ impl TestDir {
 
     /// Creates if possible a temporary directory specified in `path` relative to the current directory
     pub fn current(path: &str) -> Self {
-        let path = Path::new(path);
-        if let Ok(tempdir) = TempDir::current(path) {
-            TestDir::new(tempdir)
+        if let Ok(dir) = TestDir::builder().in_current_dir().name(path).build() {
+            dir
         } else {
             panic!("Cannot create dir in current directory")
         }
     }
 
+    /// Creates a temporary directory, builds it with `setup`, runs `f` on it and removes it,
+    /// also when `f` panics. Returns the result of `f`.
+    ///
+    /// ```
+    /// use test_dir::{TestDir,FileType,DirBuilder};
+    ///
+    /// let exists = TestDir::scoped(
+    ///     |dir| dir.create("file", FileType::EmptyFile),
+    ///     |dir| dir.path("file").exists(),
+    /// );
+    /// assert!(exists);
+    /// ```
+    pub fn scoped<R, F: FnOnce(&TestDir) -> R>(setup: impl FnOnce(TestDir) -> TestDir, f: F) -> R {
+        let dir = setup(TestDir::temp());
+        f(&dir)
+    }
+
+    /// Creates a directory with random name inside the directory named by the environment variable `var`,
+    /// or inside the system temp directory if the variable is not set or empty
+    pub fn temp_in_env(var: &str) -> std::io::Result<Self> {
+        match std::env::var_os(var) {
+            Some(base) if !base.is_empty() => TestDir::builder().in_dir(Path::new(&base)).build(),
+            _ => TestDir::builder().build(),
+        }
+    }
+
+    /// Creates a directory `sub` inside the `base` directory, or a random one if `sub` is empty
+    pub fn current_in(base: &Path, sub: &str) -> std::io::Result<Self> {
+        let builder = TestDir::builder().in_dir(base);
+        if sub.is_empty() {
+            builder.build()
+        } else {
+            builder.name(sub).build()
+        }
+    }
+
+    /// Wrap an existing directory. With `own` the directory is removed on drop,
+    /// otherwise it is left untouched.
+    pub fn from_existing(path: &Path, own: bool) -> std::io::Result<Self> {
+        if !path.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{:?} is not an existing directory", path),
+            ));
+        }
+        let tempdir = if own {
+            let mut tempdir = TempDir::create(path)?;
+            tempdir.delete = Some(path.to_path_buf());
+            Some(tempdir)
+        } else {
+            None
+        };
+        Ok(TestDir::with_root(path.to_path_buf(), tempdir))
+    }
+
     /// Returns all files created with DirBuilder
-    pub fn get_files<'a>(&self) -> &Vec<PathBuf> {
+    pub fn get_files(&self) -> &Vec<PathBuf> {
         &self.files
     }
     
     /// Returns all directories created with DirBuilder
-    pub fn get_dirs<'a>(&self) -> &Vec<PathBuf> {
+    pub fn get_dirs(&self) -> &Vec<PathBuf> {
         &self.dirs
     }
 
+    /// Returns all operations performed on the `TestDir` in order
+    #[cfg(feature = "operation-log")]
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
 
-    /*
-    fn load(&mut self, path: &Path) {
+    /// Returns all files created with DirBuilder relative to the root
+    pub fn relative_files(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .filter_map(|path| self.root_relative(path))
+            .collect()
+    }
 
+    /// Returns all directories created with DirBuilder relative to the root
+    pub fn relative_dirs(&self) -> Vec<PathBuf> {
+        self.dirs
+            .iter()
+            .filter_map(|path| self.root_relative(path))
+            .collect()
     }
-    */
 
-    // Helper functions
-    fn new(tempdir: TempDir) -> Self {
-        let root = tempdir.path().to_path_buf();
-        Self {
-            _tempdir: Some(tempdir),
-            root,
-            files: vec![],
-            dirs: vec![],
-        }
+    /// Returns the tracked files and directories that no longer exist on disk,
+    /// e.g. removed by the code under test. A broken symlink still exists.
+    pub fn validate(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .chain(self.dirs.iter())
+            .filter(|path| fs::symlink_metadata(path).is_err())
+            .cloned()
+            .collect()
     }
 
-    fn create_dir(&mut self, path: &Path) -> std::io::Result<()> {
+    /// Create a file or directory under the `path` in place, returning the error instead of panicking.
+    ///
+    /// Unlike `DirBuilder::create` it only borrows the `TestDir`, so a `TestDir` wrapped in a `Mutex`
+    /// can be shared and modified by several threads.
+    pub fn try_create(&mut self, path: &str, filetype: FileType) -> std::io::Result<()> {
+        let path = checked_path(path)?;
+        let path = path.as_path();
+        #[cfg(feature = "operation-log")]
+        let filetype_name = format!("{:?}", filetype);
+        let result = match filetype {
+            FileType::Dir => self.create_dir(path),
+            FileType::DirWithMode(mode) => {
+                self.restore_permissions();
+                self.create_dir(path)?;
+                self.set_mode(path, mode)
+            }
+            FileType::RelativeSymlink(target) => {
+                self.create_parent(path)?;
+                self.create_symlink(path, target.as_path())
+            }
+            FileType::BrokenSymlink(target) => {
+                let target = checked_path(&target)?;
+                if self.root.join(&target).symlink_metadata().is_ok() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("Target of a broken symlink {:?} exists", target),
+                    ));
+                }
+                self.create_parent(path)?;
+                // A relative link keeps pointing to the same entry when the root moves or is copied
+                let link = relative_link(path, target.as_path());
+                self.create_symlink(path, link.as_path())
+            }
+            #[cfg(all(unix, feature = "unix-special"))]
+            FileType::CharDevice { major, minor } => {
+                self.create_parent(path)?;
+                self.create_device(path, libc::S_IFCHR, major, minor)
+            }
+            #[cfg(all(unix, feature = "unix-special"))]
+            FileType::BlockDevice { major, minor } => {
+                self.create_parent(path)?;
+                self.create_device(path, libc::S_IFBLK, major, minor)
+            }
+            // Fail before anything is created on disk
+            FileType::PatternFile { ref pattern, size } if pattern.is_empty() && size > 0 => {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "PatternFile requires a non-empty pattern",
+                ))
+            }
+            _ => {
+                self.create_parent(path)?;
+                self.create_file(path, filetype)
+            }
+        };
+        result?;
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::Create {
+            path: path.to_path_buf(),
+            filetype: filetype_name,
+        });
+        Ok(())
+    }
+
+    /// Create an empty file under the `path` and return it opened for writing.
+    /// The file is tracked like any file created with `create`.
+    pub fn create_handle(&mut self, path: &str) -> std::io::Result<fs::File> {
+        let path = checked_path(path)?;
+        let path = path.as_path();
+        self.create_parent(path)?;
         let mut build_path = self.root.clone();
         build_path.push(path);
-        let result = fs::create_dir_all(build_path.as_path());
-        if let Ok(_) = result {
-            self.dirs.push(build_path);
+        let file = fs::File::create(build_path.as_path())?;
+        if let Some(mode) = self.file_mode {
+            self.set_mode(path, mode)?;
         }
-        result
+        self.files.push(build_path);
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::CreateHandle {
+            path: path.to_path_buf(),
+        });
+        Ok(file)
     }
 
-    fn create_file(&mut self, path: &Path, filetype: FileType) -> std::io::Result<()> {
+    /// Open the file under the `path` with the given `opts`, write `content` and track the file.
+    /// An escape hatch for cases `FileType` does not cover, e.g. `create_new` to fail on an existing file.
+    pub fn create_with_options(
+        mut self,
+        path: &str,
+        opts: &fs::OpenOptions,
+        content: &[u8],
+    ) -> std::io::Result<Self> {
+        let path = checked_path(path)?;
+        let path = path.as_path();
+        self.create_parent(path)?;
         let mut build_path = self.root.clone();
         build_path.push(path);
-        let file = fs::File::create(build_path.as_path());
-        if file.is_err() {
-            panic!("Create file {:?} - {:?}", build_path, file);
-        }
-        let file = file?;
-        let mut buffer = BufWriter::new(file);
-
-        match filetype {
-            FileType::EmptyFile => { /* pass */ }
-            FileType::ZeroFile(size) => {
-                for _ in 0..size {
-                    let _ = buffer.write(b"0")?;
-                }
-            }
-            FileType::RandomFile(size) => {
-                let mut numbuf: Vec<u8> = vec![];
-                let mut rng = rand::thread_rng();
-                for _ in 0..size {
-                    numbuf.push(rng.gen());
-                }
-                let _ = buffer.write(numbuf.as_slice())?;
-            }
-
-            _ => { /* Dir - already created in create_dir */ }
-        };
-        self.files.push(build_path);
-        Ok(())
+        let mut file = opts.open(build_path.as_path())?;
+        file.write_all(content)?;
+        self.finish_file(path, file)?;
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::CreateWithOptions {
+            path: path.to_path_buf(),
+        });
+        Ok(self)
     }
 
-    fn remove_file(&mut self, path: &Path) -> std::io::Result<()> {
+    /// Copy the content of the file `src` into a new file under `dest` and track it.
+    /// Only the bytes are copied, not the permissions or timestamps.
+    /// Fails with `InvalidInput` if `src` is the file under `dest` itself.
+    pub fn create_from_file(mut self, dest: &str, src: &Path) -> std::io::Result<Self> {
+        let path = checked_path(dest)?;
+        let path = path.as_path();
+        let mut source = fs::File::open(src)?;
+        self.create_parent(path)?;
         let mut build_path = self.root.clone();
         build_path.push(path);
-        if build_path.exists() {
-            if build_path.is_dir() {
-                fs::remove_dir_all(build_path)?;
-            } else if build_path.is_file() {
-                fs::remove_file(build_path)?;
+        // Creating `dest` truncates it before it could be read
+        if let Ok(existing) = fs::canonicalize(build_path.as_path()) {
+            if fs::canonicalize(src)? == existing {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Cannot create {:?} from itself", build_path),
+                ));
             }
         }
-        Ok(())
+        let mut file = fs::File::create(build_path.as_path())?;
+        std::io::copy(&mut source, &mut file)?;
+        self.finish_file(path, file)?;
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::CreateFromFile {
+            path: path.to_path_buf(),
+            src: src.to_path_buf(),
+        });
+        Ok(self)
     }
-}
 
-impl DirBuilder for TestDir {
-    /// Create a file or directory under the `path`
-    fn create(mut self, path: &str, filetype: FileType) -> Self {
-        let path = Path::new(path);
-        if path.is_absolute() {
-            panic!("Only relative paths are allowed.");
+    /// Create a file under `dest` with the content of the files under `sources` joined in order.
+    /// Panics if any of the sources does not exist or is `dest` itself.
+    pub fn concat_into(mut self, dest: &str, sources: &[&str]) -> Self {
+        let checked = |path: &str| match checked_path(path) {
+            Ok(path) => path,
+            Err(err) => panic!("Cannot concat {:?} into {:?} - {}", path, dest, err),
+        };
+        let path = checked(dest);
+        let sources: Vec<PathBuf> = sources.iter().map(|source| checked(source)).collect();
+        // Creating `dest` truncates it before it could be read
+        if sources.contains(&path) {
+            panic!("Cannot concat {:?} into itself", dest);
         }
-        if filetype == FileType::Dir {
-            let _ = self.create_dir(path).unwrap();
-        } else {
-            if let Some(p) = path.parent() {
-                let _ = self.create_dir(p).unwrap();
-            } // else { assume that current dir exists }
-            let _ = self.create_file(path, filetype).unwrap();
+        // Open every source first, a missing one leaves no partial `dest` behind
+        let mut parts = vec![];
+        for source in sources.iter() {
+            match fs::File::open(self.root.join(source)) {
+                Ok(file) => parts.push(file),
+                Err(err) => panic!("Cannot concat {:?} into {:?} - {}", source, dest, err),
+            }
         }
+        if let Err(err) = self.concat_files(&path, parts) {
+            panic!("Cannot concat into {:?} - {}", self.path(dest), err);
+        }
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::Concat { path, sources });
         self
     }
 
-    /// Remove a file or directory under the `path`
-    fn remove(mut self, path: &str) -> Self {
-        let path = Path::new(path);
-        if path.is_absolute() {
-            panic!("Only relative paths are allowed.");
+    /// Rename the root directory to the sibling `new_name`, keeping its content. Tracked paths
+    /// follow the root and the renamed directory is still removed on drop.
+    pub fn rename_root(mut self, new_name: &str) -> std::io::Result<Self> {
+        let mut components = Path::new(new_name).components();
+        let valid_name = matches!(
+            (components.next(), components.next()),
+            (Some(std::path::Component::Normal(_)), None)
+        );
+        if !valid_name {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} is not a valid directory name", new_name),
+            ));
         }
-        let remove = self.remove_file(path);
-        if remove.is_err() {
-            panic!("Cannot remove file: {:?}", remove);
+        let new_root = match self.root.parent() {
+            Some(parent) => parent.join(new_name),
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Cannot rename the root {:?}", self.root),
+                ))
+            }
+        };
+        // Renaming over an empty directory succeeds on some platforms, never replace one
+        if fs::symlink_metadata(&new_root).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Cannot rename the root to {:?}, it already exists",
+                    new_root
+                ),
+            ));
+        }
+        fs::rename(&self.root, &new_root)?;
+
+        if let Some(tempdir) = self.tempdir.as_mut() {
+            tempdir.moved_to(&new_root);
         }
+        let old_root = std::mem::replace(&mut self.root, new_root);
+        for path in self.files.iter_mut().chain(self.dirs.iter_mut()) {
+            if let Ok(relative) = path.strip_prefix(&old_root) {
+                *path = self.root.join(relative);
+            }
+        }
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::RenameRoot {
+            name: new_name.to_string(),
+        });
+        Ok(self)
+    }
+
+    /// Set the modification time of the existing file or directory under the `path` to now,
+    /// keeping the content. Read-only entries can be touched on Unix, other platforms need
+    /// a writable file and cannot touch a directory.
+    /// Panics if the entry does not exist.
+    // Mutated only to record the operation
+    #[cfg_attr(not(feature = "operation-log"), allow(unused_mut))]
+    pub fn touch(mut self, path: &str) -> Self {
+        let path = match checked_path(path) {
+            Ok(path) => path,
+            Err(err) => panic!("Cannot touch file {:?} - {}", path, err),
+        };
+        let build_path = self.root.join(&path);
+        // Setting the times only needs ownership on Unix, not write access
+        #[cfg(unix)]
+        let file = fs::File::open(build_path.as_path());
+        #[cfg(not(unix))]
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(build_path.as_path());
+        let touch = file.and_then(|file| file.set_modified(std::time::SystemTime::now()));
+        if touch.is_err() {
+            panic!("Cannot touch file {:?} - {:?}", build_path, touch);
+        }
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::Touch { path });
         self
     }
 
-    /// Prefix `path` with the current context of the DirBuilder
-    fn path(&self, path: &str) -> PathBuf {
-        let mut root = self.root.clone();
-        let path = PathBuf::from(path);
-        root.push(path);
+    /// Remove a file or directory under the `path` in place, returning the error instead of panicking.
+    pub fn try_remove(&mut self, path: &str) -> std::io::Result<()> {
+        let path = checked_path(path)?;
+        let path = path.as_path();
+        self.remove_file(path)?;
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::Remove {
+            path: path.to_path_buf(),
+        });
+        Ok(())
+    }
 
-        root
+    /// Compare the content of the root with the `expected` directory by relative paths
+    /// and byte-for-byte file content.
+    pub fn diff_against(&self, expected: &Path) -> DirDiff {
+        let mut actual_entries = BTreeMap::new();
+        let mut expected_entries = BTreeMap::new();
+        if let Err(err) = collect_entries(&self.root, &mut actual_entries) {
+            panic!("Cannot read dir {:?} - {:?}", self.root, err);
+        }
+        if let Err(err) = collect_entries(expected, &mut expected_entries) {
+            panic!("Cannot read dir {:?} - {:?}", expected, err);
+        }
+
+        let mut diff = DirDiff::default();
+        for (path, file_type) in &actual_entries {
+            match expected_entries.get(path) {
+                None => diff.only_in_actual.push(path.clone()),
+                Some(expected_type) if expected_type != file_type => {
+                    diff.content_differs.push(path.clone())
+                }
+                // Symlinks are compared by their targets, a dangling link has no content
+                Some(_) if file_type.is_symlink() => {
+                    let actual_target = fs::read_link(self.root.join(path));
+                    let expected_target = fs::read_link(expected.join(path));
+                    match (actual_target, expected_target) {
+                        (Ok(a), Ok(e)) if a == e => {}
+                        _ => diff.content_differs.push(path.clone()),
+                    }
+                }
+                Some(_) if !file_type.is_dir() => {
+                    let actual_content = fs::read(self.root.join(path));
+                    let expected_content = fs::read(expected.join(path));
+                    match (actual_content, expected_content) {
+                        (Ok(a), Ok(e)) if a == e => {}
+                        _ => diff.content_differs.push(path.clone()),
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for path in expected_entries.keys() {
+            if !actual_entries.contains_key(path) {
+                diff.only_in_expected.push(path.clone());
+            }
+        }
+        diff
     }
 
-    /// Return the root path to the temporary directory
-    fn root(&self) -> &Path {
-        self.root.as_path()
+    /// Create a file or directory under the `path` like `create`, unless the `path` already exists.
+    /// An existing entry is left untouched and is not tracked again.
+    pub fn create_if_absent(self, path: &str, filetype: FileType) -> Self {
+        // A dangling symlink exists too, creating through it would create its target
+        if self.path(path).symlink_metadata().is_ok() {
+            self
+        } else {
+            self.create(path, filetype)
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Set the permissions of the root itself to `mode` (Unix only).
+    /// The root is made writable again before it is removed on drop.
+    pub fn with_root_mode(mut self, mode: u32) -> Self {
+        self.restore_permissions();
+        let result = self.set_mode(Path::new(""), mode);
+        if result.is_err() {
+            panic!("Cannot set mode of {:?} - {:?}", self.root, result);
+        }
+        self
+    }
+
+    /// Record the deletion root into `sink` when the `TestDir` is dropped, right before removing it.
+    /// The entry is prepared here, growing the shared `Vec` is the only allocation left on drop.
+    pub fn cleanup_log(mut self, sink: Arc<Mutex<Vec<PathBuf>>>) -> Self {
+        if let Some(tempdir) = self.tempdir.as_mut() {
+            tempdir.log_cleanup(sink);
+        }
+        self
+    }
+
+    /// Keep the directory when the `TestDir` is dropped during a panic, e.g. a failed assertion,
+    /// so the fixture can be inspected. The preserved path is printed to stderr.
+    pub fn leak_on_panic(mut self) -> Self {
+        if let Some(tempdir) = self.tempdir.as_mut() {
+            tempdir.leak_on_panic = true;
+        }
+        self
+    }
+
+    /// Returns the directory removed when the `TestDir` is dropped, `None` if nothing is removed.
+    ///
+    /// It is the topmost directory created together with the root, so for `TestDir::current("a/b/c")`
+    /// with no existing `a` the whole `a` is removed, not only `a/b/c`.
+    pub fn deletion_root(&self) -> Option<&Path> {
+        self.tempdir.as_ref().and_then(TempDir::deletion_root)
+    }
+
+    /// Remove only the root on drop and keep the parent directories created together with it.
+    pub fn keep_parents(mut self) -> Self {
+        if let Some(tempdir) = self.tempdir.as_mut() {
+            tempdir.keep_parents();
+        }
+        self
+    }
+
+    /// Returns the root with all symlinks resolved, e.g. `/private/var/...` for `/var/...` on macOS.
+    /// Useful to compare the root with paths canonicalized by the code under test.
+    pub fn canonical_root(&self) -> std::io::Result<PathBuf> {
+        fs::canonicalize(&self.root)
+    }
+
+    /// Panics if the file under the `path` does not contain `needle`
+    pub fn assert_contains(&self, path: &str, needle: &[u8]) {
+        let content = self.read_for_assert(path);
+        let found = needle.is_empty() || content.windows(needle.len()).any(|w| w == needle);
+        if !found {
+            panic!(
+                "File {:?} does not contain {:?}, content: {:?}",
+                path,
+                String::from_utf8_lossy(needle),
+                snippet(&content)
+            );
+        }
+    }
+
+    /// Panics if the content of the file under the `path` is not exactly `expected`
+    pub fn assert_eq_content(&self, path: &str, expected: &[u8]) {
+        let content = self.read_for_assert(path);
+        if content != expected {
+            let offset = content
+                .iter()
+                .zip(expected)
+                .position(|(a, e)| a != e)
+                .unwrap_or_else(|| content.len().min(expected.len()));
+            panic!(
+                "File {:?} content differs at byte {} (actual len {}, expected len {}), actual: {:?}, expected: {:?}",
+                path,
+                offset,
+                content.len(),
+                expected.len(),
+                snippet(&content[offset..]),
+                snippet(&expected[offset..])
+            );
+        }
+    }
+
+    /// Copy the whole tree of `other` into the root and track the copied entries.
+    /// Directories existing in both are merged, on any other collision the entry from `other` wins.
+    pub fn merge(mut self, other: &TestDir) -> std::io::Result<Self> {
+        self.merge_dir(other.root(), Path::new(""))?;
+        #[cfg(feature = "operation-log")]
+        self.operations.push(Operation::Merge {
+            from: other.root.clone(),
+        });
+        Ok(self)
+    }
+
+    /// Visit every entry under the root recursively with its path and metadata.
+    /// Symlinks are not followed, see `walk_with`.
+    pub fn walk<F: FnMut(&Path, &fs::Metadata)>(&self, f: F) {
+        self.walk_with(false, f)
+    }
+
+    /// Visit every entry under the root recursively with its path and metadata.
+    /// With `follow_symlinks` the metadata is of the link target and linked directories are
+    /// visited too, beware of symlink cycles.
+    pub fn walk_with<F: FnMut(&Path, &fs::Metadata)>(&self, follow_symlinks: bool, mut f: F) {
+        if let Err(err) = walk_dir(&self.root, follow_symlinks, &mut f) {
+            panic!("Cannot walk dir {:?} - {:?}", self.root, err);
+        }
+    }
+
+    /// Returns the lowercase hex SHA-256 digest of the file under the `path`.
+    /// The file is streamed through the hasher, not loaded into memory.
+    #[cfg(feature = "hash")]
+    pub fn sha256(&self, path: &str) -> std::io::Result<String> {
+        use sha2::{Digest, Sha256};
+
+        let mut file = fs::File::open(self.path(path))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    /// Change the owner of the entry under the `path` to `uid` and `gid`.
+    /// Needs privileges (e.g. root), otherwise fails with `PermissionDenied`.
+    #[cfg(all(unix, feature = "chown"))]
+    pub fn with_owner(self, path: &str, uid: u32, gid: u32) -> std::io::Result<Self> {
+        let build_path = self.path(path);
+        std::os::unix::fs::chown(&build_path, Some(uid), Some(gid)).map_err(|err| {
+            if err.kind() == std::io::ErrorKind::PermissionDenied {
+                std::io::Error::new(
+                    err.kind(),
+                    format!(
+                        "Changing the owner of {:?} to {}:{} needs privileges - {}",
+                        build_path, uid, gid, err
+                    ),
+                )
+            } else {
+                err
+            }
+        })?;
+        Ok(self)
+    }
+
+    /// Panics if the permission bits of the entry under the `path` are not `expected`
+    #[cfg(unix)]
+    pub fn assert_mode(&self, path: &str, expected: u32) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = match self.path(path).metadata() {
+            Ok(metadata) => metadata.permissions().mode() & 0o7777,
+            Err(err) => panic!("Cannot read metadata of {:?} - {:?}", path, err),
+        };
+        if mode != expected {
+            panic!(
+                "File {:?} has mode {:#o}, expected {:#o}",
+                path, mode, expected
+            );
+        }
+    }
+
+    /// Returns the number of lines of the file under the `path`. A last line without
+    /// a trailing newline is counted, an empty file has no lines.
+    pub fn line_count(&self, path: &str) -> std::io::Result<usize> {
+        let mut reader = std::io::BufReader::new(fs::File::open(self.path(path))?);
+        let mut count = 0;
+        let mut last = b'\n';
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            count += buffer.iter().filter(|b| **b == b'\n').count();
+            last = buffer[buffer.len() - 1];
+            let len = buffer.len();
+            reader.consume(len);
+        }
+        if last != b'\n' {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns the sorted names of the immediate children of the directory under the `path`,
+    /// the one level counterpart of `walk`. Fails if the `path` is not a directory.
+    pub fn read_dir_names(&self, path: &str) -> std::io::Result<Vec<String>> {
+        let mut names = fs::read_dir(self.path(path))?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Panics if the content of the file under the `path` is not valid UTF-8
+    pub fn assert_utf8(&self, path: &str) {
+        let content = self.read_for_assert(path);
+        if let Err(err) = std::str::from_utf8(&content) {
+            panic!(
+                "File {:?} is not valid UTF-8, invalid byte at {}, content: {:?}",
+                path,
+                err.valid_up_to(),
+                snippet(&content)
+            );
+        }
+    }
+
+    /// Strip the root from the absolute path `abs`, the inverse of `path`.
+    /// Returns `None` if `abs` is not under the root.
+    pub fn root_relative(&self, abs: &Path) -> Option<PathBuf> {
+        abs.strip_prefix(&self.root).ok().map(PathBuf::from)
+    }
+
+    /// Flush and fsync every file created from now on, so its content is on disk when `create` returns.
+    /// Useful when the code under test (e.g. a file watcher) starts right after the fixture is built.
+    pub fn sync_writes(mut self) -> Self {
+        self.sync_writes = true;
+        self
+    }
+
+    /// Create missing parent directories of created files and directories (default), or fail
+    /// with `NotFound` if `enabled` is false
+    pub fn ensure_parent(mut self, enabled: bool) -> Self {
+        self.ensure_parent = enabled;
+        self
+    }
+
+    /// Set the permissions of every file created from now on to `mode` (Unix only)
+    pub fn default_file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// Set the permissions of every directory created from now on to `mode` (Unix only).
+    /// `FileType::DirWithMode` overrides it. The directories are made writable again before
+    /// they are removed on drop.
+    pub fn default_dir_mode(mut self, mode: u32) -> Self {
+        self.restore_permissions();
+        self.dir_mode = Some(mode);
+        self
+    }
+
+    /// Create all directories in `paths`. Only the given directories are tracked, not the missing parents.
+    pub fn create_dirs(mut self, paths: &[&str]) -> Self {
+        for path in paths {
+            self = self.create(path, FileType::Dir);
+        }
+        self
+    }
+
+    /// Create the directory `dir` and all `files` inside it, the file paths are relative to `dir`
+    pub fn populate(mut self, dir: &str, files: Vec<(&str, FileType)>) -> Self {
+        self = self.create(dir, FileType::Dir);
+        for (name, filetype) in files {
+            let path = Path::new(dir).join(name);
+            self = self.create(&path.to_string_lossy(), filetype);
+        }
+        self
+    }
+
+    /// Create a file for every index in `range`. The `{}` in `template` is replaced with the index
+    /// and `content` generates the file content for the index.
+    ///
+    /// Panics if `template` does not contain exactly one `{}`.
+    pub fn create_numbered(
+        mut self,
+        template: &str,
+        range: std::ops::Range<usize>,
+        content: impl Fn(usize) -> Vec<u8>,
+    ) -> Self {
+        if template.matches("{}").count() != 1 {
+            panic!("Template {:?} must contain exactly one \"{{}}\"", template);
+        }
+        for index in range {
+            let path = template.replace("{}", &index.to_string());
+            self = self.create(&path, FileType::ContentFile(content(index)));
+        }
+        self
+    }
+
+    /// Remove every file or directory under the root matching the glob `pattern`.
+    /// Does nothing if no entry matches.
+    #[cfg(feature = "glob")]
+    pub fn remove_matching(mut self, pattern: &str) -> Self {
+        if Path::new(pattern).is_absolute() {
+            panic!("Only relative paths are allowed.");
+        }
+        if normalize(Path::new(pattern)).is_none() {
+            panic!("Path {:?} escapes the root", pattern);
+        }
+        let full_pattern = format!(
+            "{}/{}",
+            glob::Pattern::escape(&self.root.to_string_lossy()),
+            pattern
+        );
+        let entries = match glob::glob(&full_pattern) {
+            Ok(entries) => entries,
+            Err(err) => panic!("Invalid pattern {:?}: {}", pattern, err),
+        };
+        // Collect first, removing a directory invalidates the paths below it
+        let matched: Vec<PathBuf> = entries.filter_map(Result::ok).collect();
+        for path in matched {
+            // A pattern like "." or "a/.." matches the root itself, which is never removed
+            let relative = match path.strip_prefix(&self.root).ok().and_then(normalize) {
+                Some(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => continue,
+            };
+            let remove = self.remove_file(relative.as_path());
+            if remove.is_err() {
+                panic!("Cannot remove file: {:?}", remove);
+            }
+            self.untrack(self.root.join(&relative).as_path());
+            #[cfg(feature = "operation-log")]
+            self.operations.push(Operation::Remove { path: relative });
+        }
+        self
+    }
+
+    /*
+    fn load(&mut self, path: &Path) {
+
+    }
+    */
+
+    // Helper functions
+    fn new(tempdir: TempDir) -> Self {
+        let root = tempdir.path();
+        TestDir::with_root(root, Some(tempdir))
+    }
+
+    fn with_root(root: PathBuf, tempdir: Option<TempDir>) -> Self {
+        Self {
+            tempdir,
+            root,
+            files: vec![],
+            dirs: vec![],
+            rng: None,
+            sync_writes: false,
+            file_mode: None,
+            dir_mode: None,
+            ensure_parent: true,
+            #[cfg(feature = "operation-log")]
+            operations: vec![],
+        }
+    }
+
+    // Make the whole tree writable again on drop, a directory mode may prevent removing it
+    fn restore_permissions(&mut self) {
+        if let Some(tempdir) = self.tempdir.as_mut() {
+            tempdir.restore_permissions = true;
+        }
+    }
+
+    fn read_for_assert(&self, path: &str) -> Vec<u8> {
+        match fs::read(self.path(path)) {
+            Ok(content) => content,
+            Err(err) => panic!("Cannot read file {:?} - {:?}", path, err),
+        }
+    }
+
+    fn create_dir(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        // Directories this call creates, deepest first. Existing ones keep their mode and
+        // the root itself is not part of the fixture.
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !self.root.join(p).exists())
+            .collect();
+        if self.ensure_parent {
+            fs::create_dir_all(build_path.as_path())?;
+        } else if let Err(err) = fs::create_dir(build_path.as_path()) {
+            // Like `create_dir_all`, an existing directory is not an error
+            if err.kind() != std::io::ErrorKind::AlreadyExists || !build_path.is_dir() {
+                return Err(err);
+            }
+        }
+        if let Some(mode) = self.dir_mode {
+            for dir in missing {
+                self.set_mode(dir, mode)?;
+            }
+        }
+        self.dirs.push(build_path);
+        Ok(())
+    }
+
+    // Create the missing parent directories of `path`. A path at the root has an empty parent,
+    // the root always exists.
+    fn create_parent(&mut self, path: &Path) -> std::io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && self.ensure_parent => {
+                self.create_dir(parent)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&self, path: &Path, mode: u32) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        fs::set_permissions(build_path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(&self, _path: &Path, _mode: u32) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn create_file(&mut self, path: &Path, filetype: FileType) -> std::io::Result<()> {
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        let file = fs::File::create(build_path.as_path())?;
+        let mut buffer = BufWriter::new(file);
+
+        match filetype {
+            // A zero size is an empty file, nothing to generate
+            FileType::EmptyFile
+            | FileType::ZeroFile(0)
+            | FileType::NullFile(0)
+            | FileType::RandomFile(0) => { /* pass */ }
+            FileType::ZeroFile(size) => {
+                write_chunks(&mut buffer, size, CHUNK_SIZE, |chunk| {
+                    chunk.fill(b'0');
+                    Ok(())
+                })?;
+            }
+            FileType::NullFile(size) => {
+                write_chunks(&mut buffer, size, CHUNK_SIZE, |chunk| {
+                    chunk.fill(0);
+                    Ok(())
+                })?;
+            }
+            FileType::RandomFile(size) => match self.rng.as_mut() {
+                Some(rng) => write_chunks(&mut buffer, size, CHUNK_SIZE, |chunk| {
+                    rng.fill(chunk);
+                    Ok(())
+                })?,
+                // Few large syscalls are much faster than generating small chunks
+                None => write_chunks(&mut buffer, size, RANDOM_CHUNK_SIZE, |chunk| {
+                    getrandom::getrandom(chunk).map_err(std::io::Error::from)
+                })?,
+            },
+            FileType::ContentFile(content) => {
+                buffer.write_all(content.as_slice())?;
+            }
+            FileType::Utf8File(text) => {
+                buffer.write_all(text.as_bytes())?;
+            }
+            FileType::TruncatedFile { content, len } => {
+                buffer.write_all(content.as_slice())?;
+                buffer.flush()?;
+                buffer.get_ref().set_len(len)?;
+            }
+            FileType::ContentFromIter(chunks) => {
+                for chunk in chunks {
+                    buffer.write_all(chunk.as_slice())?;
+                }
+            }
+            #[cfg(feature = "json")]
+            FileType::JsonFile(value) => {
+                serde_json::to_writer_pretty(&mut buffer, &value)?;
+            }
+            #[cfg(feature = "gzip")]
+            FileType::GzipFile(content) => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(&mut buffer, flate2::Compression::default());
+                encoder.write_all(content.as_slice())?;
+                encoder.finish()?;
+            }
+            FileType::RepeatedFile { block, count } => {
+                let size = block.len() as u64 * count as u64;
+                write_pattern(&mut buffer, block.as_slice(), size)?;
+            }
+            FileType::PatternFile { pattern, size } => {
+                write_pattern(&mut buffer, pattern.as_slice(), size)?;
+            }
+
+            _ => { /* Dir, DirWithMode, symlinks, devices - created in try_create */ }
+        };
+        self.finish_file(path, buffer.into_inner()?)
+    }
+
+    // Fsync the written `file` if requested, close it, apply the default mode and track it.
+    // Every path writing the content of a file ends here.
+    fn finish_file(&mut self, path: &Path, file: fs::File) -> std::io::Result<()> {
+        if self.sync_writes {
+            file.sync_all()?;
+        }
+        // Close the file before returning, an open handle blocks renaming and removing on Windows
+        drop(file);
+        if let Some(mode) = self.file_mode {
+            self.set_mode(path, mode)?;
+        }
+        self.files.push(self.root.join(path));
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, path: &Path, target: &Path) -> std::io::Result<()> {
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        #[cfg(unix)]
+        let result = std::os::unix::fs::symlink(target, build_path.as_path());
+        #[cfg(windows)]
+        let result = std::os::windows::fs::symlink_file(target, build_path.as_path());
+        #[cfg(not(any(unix, windows)))]
+        let result = Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Symlinks are not supported on this platform",
+        ));
+        result?;
+        self.files.push(build_path);
+        Ok(())
+    }
+
+    #[cfg(all(unix, feature = "unix-special"))]
+    fn create_device(
+        &mut self,
+        path: &Path,
+        kind: libc::mode_t,
+        major: u32,
+        minor: u32,
+    ) -> std::io::Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        let c_path = std::ffi::CString::new(build_path.as_os_str().as_bytes())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        let dev = libc::makedev(major as _, minor as _);
+        let result = unsafe { libc::mknod(c_path.as_ptr(), kind | 0o644, dev) };
+        if result != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(std::io::Error::new(
+                    err.kind(),
+                    format!(
+                        "Creating device node {:?} needs privileges - {}",
+                        build_path, err
+                    ),
+                ));
+            }
+            return Err(err);
+        }
+        self.files.push(build_path);
+        Ok(())
+    }
+
+    fn merge_dir(&mut self, source_root: &Path, relative: &Path) -> std::io::Result<()> {
+        for entry in fs::read_dir(source_root.join(relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            let source = source_root.join(path.as_path());
+            let dest = self.root.join(path.as_path());
+            let file_type = entry.file_type()?;
+
+            // Directories are merged, any other existing entry is replaced
+            if let Ok(metadata) = dest.symlink_metadata() {
+                if !(file_type.is_dir() && metadata.is_dir()) {
+                    if metadata.is_dir() {
+                        fs::remove_dir_all(dest.as_path())?;
+                    } else {
+                        fs::remove_file(dest.as_path())?;
+                    }
+                    self.untrack(dest.as_path());
+                }
+            }
+
+            if file_type.is_dir() {
+                fs::create_dir_all(dest.as_path())?;
+                if !self.dirs.contains(&dest) {
+                    self.dirs.push(dest);
+                }
+                self.merge_dir(source_root, path.as_path())?;
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(source.as_path())?;
+                self.create_symlink(path.as_path(), target.as_path())?;
+            } else {
+                fs::copy(source.as_path(), dest.as_path())?;
+                self.files.push(dest);
+            }
+        }
+        Ok(())
+    }
+
+    fn concat_files(&mut self, path: &Path, mut parts: Vec<fs::File>) -> std::io::Result<()> {
+        self.create_parent(path)?;
+        let build_path = self.root.join(path);
+        let mut file = BufWriter::new(fs::File::create(build_path.as_path())?);
+        for part in parts.iter_mut() {
+            std::io::copy(part, &mut file)?;
+        }
+        self.finish_file(path, file.into_inner()?)
+    }
+
+    fn untrack(&mut self, path: &Path) {
+        self.files.retain(|p| !p.starts_with(path));
+        self.dirs.retain(|p| !p.starts_with(path));
+    }
+
+    fn remove_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut build_path = self.root.clone();
+        build_path.push(path);
+        // Do not follow symlinks, a dangling link must be removed as well
+        let metadata = match build_path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if metadata.is_dir() {
+            fs::remove_dir_all(build_path)
+        } else {
+            fs::remove_file(build_path)
+        }
+    }
+}
+
+impl DirBuilder for TestDir {
+    /// Create a file or directory under the `path`
+    fn create(mut self, path: &str, filetype: FileType) -> Self {
+        if Path::new(path).is_absolute() {
+            panic!("Only relative paths are allowed.");
+        }
+        let create = self.try_create(path, filetype);
+        if create.is_err() {
+            panic!("Create file {:?} - {:?}", self.root.join(path), create);
+        }
+        self
+    }
+
+    /// Remove a file or directory under the `path`
+    fn remove(mut self, path: &str) -> Self {
+        if Path::new(path).is_absolute() {
+            panic!("Only relative paths are allowed.");
+        }
+        let remove = self.try_remove(path);
+        if remove.is_err() {
+            panic!("Cannot remove file: {:?}", remove);
+        }
+        self
+    }
+
+    /// Prefix `path` with the current context of the DirBuilder
+    ///
+    /// Panics if the path escapes the root
+    fn path(&self, path: &str) -> PathBuf {
+        let mut root = self.root.clone();
+        let path = match normalize(Path::new(path)) {
+            Some(path) => path,
+            None => panic!("Path {:?} escapes the root", path),
+        };
+        root.push(path);
+
+        root
+    }
+
+    /// Return the root path to the temporary directory
+    fn root(&self) -> &Path {
+        self.root.as_path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
 
+    #[cfg(unix)]
+    fn is_root() -> bool {
+        unsafe { libc::geteuid() == 0 }
+    }
+
     #[test]
     fn test_testdir_temp_dir() {
         let path;
@@ -382,14 +1990,14 @@ mod tests {
         let dir = dir.create(name, FileType::RandomFile(len));
         assert!(dir.path(name).exists());
         assert!(dir.path(name).is_file());
-        assert_eq!(dir.path(name).metadata().unwrap().len(), len as u64);
+        assert_eq!(dir.path(name).metadata().unwrap().len(), len);
 
         let name = "zero";
         let len = 1024;
         let dir = dir.create(name, FileType::ZeroFile(len));
         assert!(dir.path(name).exists());
         assert!(dir.path(name).is_file());
-        assert_eq!(dir.path(name).metadata().unwrap().len(), len as u64);
+        assert_eq!(dir.path(name).metadata().unwrap().len(), len);
     }
 
     #[test]
@@ -403,4 +2011,1368 @@ mod tests {
         let dir = dir.remove(name);
         assert!(!dir.path(name).exists());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_dir_with_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TestDir::temp().create("parent/readonly", FileType::DirWithMode(0o555));
+
+        let mode = dir
+            .path("parent/readonly")
+            .metadata()
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o555);
+        // Intermediate directories keep the default permissions
+        let mode = dir.path("parent").metadata().unwrap().permissions().mode();
+        assert_ne!(mode & 0o777, 0o555);
+
+        // Root ignores permission bits
+        if !is_root() {
+            assert!(fs::File::create(dir.path("parent/readonly/file")).is_err());
+        }
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn test_testdir_remove_matching() {
+        let dir = TestDir::temp()
+            .create("log1.txt", FileType::EmptyFile)
+            .create("log2.txt", FileType::EmptyFile)
+            .create("keep.dat", FileType::EmptyFile)
+            .remove_matching("*.txt");
+
+        assert!(!dir.path("log1.txt").exists());
+        assert!(!dir.path("log2.txt").exists());
+        assert!(dir.path("keep.dat").exists());
+        assert_eq!(dir.get_files(), &vec![dir.path("keep.dat")]);
+
+        // No match is not an error
+        let dir = dir.remove_matching("*.none");
+        assert!(dir.path("keep.dat").exists());
+
+        // Patterns matching the root leave it in place
+        let dir = dir
+            .remove_matching("")
+            .remove_matching(".")
+            .remove_matching("*/..");
+        assert!(dir.path("keep.dat").exists());
+        assert_eq!(dir.get_files(), &vec![dir.path("keep.dat")]);
+    }
+
+    #[cfg(all(unix, feature = "glob"))]
+    #[test]
+    fn test_testdir_remove_matching_broken_symlink() {
+        // Dangling symlinks are removed, not only untracked
+        let dir = TestDir::temp()
+            .create("keep.dat", FileType::EmptyFile)
+            .create(
+                "dangling",
+                FileType::BrokenSymlink(PathBuf::from("missing")),
+            )
+            .remove_matching("dang*");
+        assert!(dir.path("dangling").symlink_metadata().is_err());
+        assert_eq!(dir.get_files(), &vec![dir.path("keep.dat")]);
+    }
+
+    #[test]
+    fn test_testdir_builder() {
+        let first = TestDir::builder()
+            .prefix("fixture_")
+            .seed(7)
+            .build()
+            .unwrap()
+            .create("random", FileType::RandomFile(64));
+
+        let name = first.root().file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("fixture_"));
+        assert_eq!(name.len(), "fixture_".len() + DEFAULT_ENTROPY);
+        assert!(first.root().starts_with(std::env::temp_dir()));
+
+        // Same seed, same content
+        let second = TestDir::builder()
+            .seed(7)
+            .build()
+            .unwrap()
+            .create("random", FileType::RandomFile(64));
+        assert_eq!(
+            fs::read(first.path("random")).unwrap(),
+            fs::read(second.path("random")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_testdir_create_numbered() {
+        let dir = TestDir::temp().create_numbered("logs/file{}.txt", 0..3, |i| {
+            format!("line {}", i).into_bytes()
+        });
+
+        for i in 0..3 {
+            let path = dir.path(&format!("logs/file{}.txt", i));
+            assert_eq!(fs::read(path).unwrap(), format!("line {}", i).into_bytes());
+        }
+        assert!(!dir.path("logs/file3.txt").exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "must contain exactly one")]
+    fn test_testdir_create_numbered_bad_template() {
+        let _ = TestDir::temp().create_numbered("file{}{}.txt", 0..1, |_| vec![]);
+    }
+
+    #[test]
+    fn test_testdir_sync_writes() {
+        // The content must be visible to any reader as soon as create returns
+        let source =
+            TestDir::temp().create("golden.txt", FileType::ContentFile(b"golden".to_vec()));
+        let dir = TestDir::temp()
+            .sync_writes()
+            .create("synced", FileType::ContentFile(b"content".to_vec()))
+            .concat_into("concat", &["synced", "synced"])
+            .create_from_file("copied", &source.path("golden.txt"))
+            .unwrap()
+            .create_with_options(
+                "opened",
+                fs::OpenOptions::new().write(true).create(true),
+                b"opened",
+            )
+            .unwrap();
+
+        assert_eq!(fs::read(dir.path("synced")).unwrap(), b"content");
+        assert_eq!(fs::read(dir.path("concat")).unwrap(), b"contentcontent");
+        assert_eq!(fs::read(dir.path("copied")).unwrap(), b"golden");
+        assert_eq!(fs::read(dir.path("opened")).unwrap(), b"opened");
+    }
+
+    #[test]
+    fn test_testdir_root_relative() {
+        let dir = TestDir::temp();
+
+        let path = dir.path("a/b/file");
+        assert_eq!(dir.root_relative(&path), Some(PathBuf::from("a/b/file")));
+        assert_eq!(dir.root_relative(dir.root()), Some(PathBuf::new()));
+
+        let outside = std::env::current_dir().unwrap();
+        assert_eq!(dir.root_relative(&outside), None);
+    }
+
+    #[test]
+    fn test_testdir_pattern_file() {
+        let dir = TestDir::temp().create(
+            "pattern",
+            FileType::PatternFile {
+                pattern: vec![0xDE, 0xAD],
+                size: 5,
+            },
+        );
+
+        assert_eq!(
+            fs::read(dir.path("pattern")).unwrap(),
+            vec![0xDE, 0xAD, 0xDE, 0xAD, 0xDE]
+        );
+
+        // Content spanning several chunks stays aligned to the pattern
+        let size = CHUNK_SIZE * 2 + 1;
+        let dir = dir.create(
+            "large",
+            FileType::PatternFile {
+                pattern: b"abc".to_vec(),
+                size,
+            },
+        );
+        let content = fs::read(dir.path("large")).unwrap();
+        assert_eq!(content.len() as u64, size);
+        assert!(content.chunks(3).all(|part| b"abc".starts_with(part)));
+
+        // An empty pattern is rejected without leaving a file behind
+        let mut dir = dir;
+        let err = dir
+            .try_create(
+                "empty",
+                FileType::PatternFile {
+                    pattern: vec![],
+                    size: 3,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(dir.path("empty").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_testdir_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TestDir>();
+
+        let dir = Arc::new(Mutex::new(TestDir::temp()));
+        let root = dir.lock().unwrap().root().to_path_buf();
+
+        let worker = {
+            let dir = Arc::clone(&dir);
+            std::thread::spawn(move || {
+                for i in 0..10 {
+                    let name = format!("worker/file{}", i);
+                    dir.lock()
+                        .unwrap()
+                        .try_create(&name, FileType::EmptyFile)
+                        .unwrap();
+                }
+            })
+        };
+        for _ in 0..10 {
+            assert_eq!(dir.lock().unwrap().root(), root.as_path());
+        }
+        worker.join().unwrap();
+
+        let mut dir = dir.lock().unwrap();
+        assert_eq!(dir.get_files().len(), 10);
+        dir.try_remove("worker").unwrap();
+        assert!(!dir.path("worker").exists());
+        assert!(dir.try_create("/absolute", FileType::EmptyFile).is_err());
+    }
+
+    #[test]
+    fn test_testdir_diff_against() {
+        let dir = TestDir::temp()
+            .create("same", FileType::ContentFile(b"same".to_vec()))
+            .create("sub/changed", FileType::ContentFile(b"old".to_vec()))
+            .create("extra", FileType::EmptyFile);
+
+        let expected = TestDir::temp()
+            .create("same", FileType::ContentFile(b"same".to_vec()))
+            .create("sub/changed", FileType::ContentFile(b"old".to_vec()))
+            .create("extra", FileType::EmptyFile);
+        assert!(dir.diff_against(expected.root()).is_empty());
+
+        let expected = expected
+            .remove("extra")
+            .create("sub/changed", FileType::ContentFile(b"new".to_vec()))
+            .create("missing/file", FileType::EmptyFile);
+        let diff = dir.diff_against(expected.root());
+        assert_eq!(diff.only_in_actual, vec![PathBuf::from("extra")]);
+        assert_eq!(
+            diff.only_in_expected,
+            vec![PathBuf::from("missing"), PathBuf::from("missing/file")]
+        );
+        assert_eq!(diff.content_differs, vec![PathBuf::from("sub/changed")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_diff_against_symlinks() {
+        // Symlinks are compared by target, even dangling ones
+        let link = |target: &str| FileType::RelativeSymlink(PathBuf::from(target));
+        let dir = TestDir::temp()
+            .create("dangling", link("missing"))
+            .create("changed", link("old"));
+        let expected = TestDir::temp()
+            .create("dangling", link("missing"))
+            .create("changed", link("new"));
+        let diff = dir.diff_against(expected.root());
+        assert_eq!(diff.content_differs, vec![PathBuf::from("changed")]);
+    }
+
+    #[test]
+    fn test_testdir_large_size() {
+        // Only check the type, do not write 4 GiB
+        let size = u64::from(u32::MAX) + 1;
+        match FileType::RandomFile(size) {
+            FileType::RandomFile(s) => assert!(s > u64::from(u32::MAX)),
+            _ => unreachable!(),
+        }
+
+        // Sizes spanning several chunks
+        let len = 2 * CHUNK_SIZE + 1;
+        let dir = TestDir::temp()
+            .create("random", FileType::RandomFile(len))
+            .create("zero", FileType::ZeroFile(len));
+        assert_eq!(dir.path("random").metadata().unwrap().len(), len);
+        let zero = fs::read(dir.path("zero")).unwrap();
+        assert_eq!(zero.len() as u64, len);
+        assert!(zero.iter().all(|b| *b == b'0'));
+    }
+
+    #[test]
+    fn test_testdir_create_if_absent() {
+        let dir = TestDir::temp()
+            .create("file", FileType::ContentFile(b"original".to_vec()))
+            .create_if_absent("file", FileType::ContentFile(b"other".to_vec()))
+            .create_if_absent("new", FileType::EmptyFile);
+
+        assert_eq!(fs::read(dir.path("file")).unwrap(), b"original");
+        assert!(dir.path("new").exists());
+        assert_eq!(dir.get_files(), &vec![dir.path("file"), dir.path("new")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_create_if_absent_broken_symlink() {
+        // Creating through a dangling symlink would create its target
+        let dir = TestDir::temp()
+            .create(
+                "dangling",
+                FileType::BrokenSymlink(PathBuf::from("missing")),
+            )
+            .create_if_absent("dangling", FileType::EmptyFile);
+        assert!(dir.path("missing").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_testdir_keep_parents() {
+        let current_dir = std::env::current_dir().unwrap();
+        let parent = current_dir.join("keep_parents");
+        {
+            let dir = TestDir::current("keep_parents/b/c");
+            assert_eq!(dir.deletion_root(), Some(parent.as_path()));
+
+            let dir = dir.keep_parents();
+            assert_eq!(dir.deletion_root(), Some(dir.root()));
+        }
+        assert!(parent.join("b").exists());
+        assert!(!parent.join("b/c").exists());
+        fs::remove_dir_all(parent).unwrap();
+    }
+
+    #[test]
+    fn test_testdir_truncated_file() {
+        let dir = TestDir::temp()
+            .create(
+                "shrunk",
+                FileType::TruncatedFile {
+                    content: b"0123456789".to_vec(),
+                    len: 4,
+                },
+            )
+            .create(
+                "extended",
+                FileType::TruncatedFile {
+                    content: b"0123".to_vec(),
+                    len: 10,
+                },
+            );
+
+        assert_eq!(fs::read(dir.path("shrunk")).unwrap(), b"0123");
+        assert_eq!(fs::read(dir.path("extended")).unwrap(), b"0123\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn test_testdir_content_from_iter() {
+        let chunks = vec![b"first ".to_vec(), b"second ".to_vec(), b"third".to_vec()];
+        let dir = TestDir::temp().create(
+            "chunks",
+            FileType::ContentFromIter(Box::new(chunks.into_iter())),
+        );
+
+        assert_eq!(fs::read(dir.path("chunks")).unwrap(), b"first second third");
+        assert_eq!(
+            format!(
+                "{:?}",
+                FileType::ContentFromIter(Box::new(std::iter::empty()))
+            ),
+            "ContentFromIter(..)"
+        );
+        assert_eq!(FileType::ZeroFile(1), FileType::ZeroFile(1));
+    }
+
+    #[test]
+    fn test_testdir_canonical_root() {
+        let dir = TestDir::temp().create("file", FileType::EmptyFile);
+
+        let canonical = dir.canonical_root().unwrap();
+        assert!(canonical.is_absolute());
+        assert_eq!(canonical, fs::canonicalize(dir.root()).unwrap());
+        // Paths reported by canonicalizing code are under the canonical root
+        assert!(fs::canonicalize(dir.path("file"))
+            .unwrap()
+            .starts_with(&canonical));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_relative_symlink() {
+        let dir = TestDir::temp()
+            .create("b", FileType::ContentFile(b"target".to_vec()))
+            .create("a/link", FileType::RelativeSymlink(PathBuf::from("../b")));
+
+        assert_eq!(
+            fs::read_link(dir.path("a/link")).unwrap(),
+            PathBuf::from("../b")
+        );
+        assert_eq!(fs::read(dir.path("a/link")).unwrap(), b"target");
+        assert!(dir.get_files().contains(&dir.path("a/link")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_with_root_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path;
+        {
+            let dir = TestDir::temp().with_root_mode(0o555);
+
+            let mode = dir.root().metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o555);
+            // Root ignores permission bits
+            if !is_root() {
+                assert!(fs::File::create(dir.path("file")).is_err());
+            }
+            path = dir.root().to_path_buf();
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_testdir_create_remove_loop() {
+        // Fails on Windows if the file handle is still open after create
+        let mut dir = TestDir::temp();
+        for _ in 0..100 {
+            dir = dir
+                .create("file", FileType::ContentFile(b"content".to_vec()))
+                .remove("file");
+        }
+        assert!(!dir.path("file").exists());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_testdir_json_file() {
+        let value = serde_json::json!({"a": 1});
+        let dir = TestDir::temp().create("config.json", FileType::JsonFile(value.clone()));
+
+        let content = fs::read(dir.path("config.json")).unwrap();
+        let read: serde_json::Value = serde_json::from_slice(&content).unwrap();
+        assert_eq!(read, value);
+    }
+
+    #[test]
+    fn test_testdir_create_handle() {
+        let mut dir = TestDir::temp();
+
+        let mut file = dir.create_handle("logs/growing.log").unwrap();
+        file.write_all(b"first\n").unwrap();
+        assert_eq!(fs::read(dir.path("logs/growing.log")).unwrap(), b"first\n");
+        file.write_all(b"second\n").unwrap();
+        drop(file);
+
+        assert_eq!(
+            fs::read(dir.path("logs/growing.log")).unwrap(),
+            b"first\nsecond\n"
+        );
+        assert!(dir.get_files().contains(&dir.path("logs/growing.log")));
+    }
+
+    #[test]
+    fn test_testdir_assert_content() {
+        let dir = TestDir::temp().create("out", FileType::ContentFile(b"hello world".to_vec()));
+
+        dir.assert_contains("out", b"lo wo");
+        dir.assert_eq_content("out", b"hello world");
+    }
+
+    #[test]
+    #[should_panic(expected = "does not contain \"bye\", content: \"hello world\"")]
+    fn test_testdir_assert_contains_panic() {
+        let dir = TestDir::temp().create("out", FileType::ContentFile(b"hello world".to_vec()));
+        dir.assert_contains("out", b"bye");
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "differs at byte 6 (actual len 11, expected len 11), actual: \"world\", expected: \"there\""
+    )]
+    fn test_testdir_assert_eq_content_panic() {
+        let dir = TestDir::temp().create("out", FileType::ContentFile(b"hello world".to_vec()));
+        dir.assert_eq_content("out", b"hello there");
+    }
+
+    #[cfg(all(unix, feature = "unix-special"))]
+    #[test]
+    fn test_testdir_char_device() {
+        use std::os::unix::fs::FileTypeExt;
+
+        // Without privileges only the error path can be checked
+        let mut dir = TestDir::temp();
+        match dir.try_create("dev/null", FileType::CharDevice { major: 1, minor: 3 }) {
+            Ok(()) => {
+                let metadata = dir.path("dev/null").symlink_metadata().unwrap();
+                assert!(metadata.file_type().is_char_device());
+            }
+            Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied),
+        }
+    }
+
+    #[cfg(all(unix, feature = "chown"))]
+    #[test]
+    fn test_testdir_with_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only root can give away a file, otherwise the error path is checked
+        let dir = TestDir::temp().create("owned", FileType::EmptyFile);
+        let path = dir.path("owned");
+        match dir.with_owner("owned", 1234, 5678) {
+            Ok(_dir) => {
+                let metadata = fs::metadata(&path).unwrap();
+                assert_eq!((metadata.uid(), metadata.gid()), (1234, 5678));
+            }
+            Err(err) => {
+                assert!(!is_root());
+                assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+            }
+        }
+    }
+
+    #[test]
+    fn test_testdir_merge() {
+        let other = TestDir::temp()
+            .create(
+                "shared/from_other",
+                FileType::ContentFile(b"other".to_vec()),
+            )
+            .create("conflict", FileType::ContentFile(b"other".to_vec()))
+            .create("only_other", FileType::EmptyFile);
+
+        let dir = TestDir::temp()
+            .create("shared/from_self", FileType::ContentFile(b"self".to_vec()))
+            .create("conflict", FileType::ContentFile(b"self".to_vec()))
+            .create("only_self", FileType::EmptyFile)
+            .merge(&other)
+            .unwrap();
+
+        // Colliding directories are merged
+        assert_eq!(fs::read(dir.path("shared/from_self")).unwrap(), b"self");
+        assert_eq!(fs::read(dir.path("shared/from_other")).unwrap(), b"other");
+        // Colliding files are replaced
+        assert_eq!(fs::read(dir.path("conflict")).unwrap(), b"other");
+        assert!(dir.path("only_self").exists());
+        assert!(dir.path("only_other").exists());
+
+        let files = dir.get_files();
+        assert_eq!(files.len(), 5);
+        assert_eq!(
+            files.iter().filter(|f| **f == dir.path("conflict")).count(),
+            1
+        );
+        assert!(files.contains(&dir.path("shared/from_other")));
+    }
+
+    #[test]
+    fn test_testdir_current_in() {
+        let base = TestDir::temp();
+
+        let path;
+        {
+            let dir = TestDir::current_in(base.root(), "fixture/sub").unwrap();
+            assert_eq!(dir.root(), base.path("fixture/sub"));
+            assert!(dir.root().exists());
+            path = base.path("fixture");
+        }
+        assert!(!path.exists());
+
+        let dir = TestDir::current_in(base.root(), "").unwrap();
+        assert_eq!(dir.root().parent(), Some(base.root()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_default_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: PathBuf| path.metadata().unwrap().permissions().mode() & 0o777;
+
+        let dir = TestDir::temp()
+            .default_file_mode(0o600)
+            .default_dir_mode(0o750)
+            .create("first", FileType::EmptyFile)
+            .create("sub/second", FileType::RandomFile(10))
+            .create("override", FileType::DirWithMode(0o700));
+
+        assert_eq!(mode(dir.path("first")), 0o600);
+        assert_eq!(mode(dir.path("sub/second")), 0o600);
+        assert_eq!(mode(dir.path("sub")), 0o750);
+        assert_eq!(mode(dir.path("override")), 0o700);
+
+        // The default applies to every created intermediate directory
+        let dir = dir.create("x/y/z/file", FileType::EmptyFile);
+        for path in &["x", "x/y", "x/y/z"] {
+            assert_eq!(mode(dir.path(path)), 0o750);
+        }
+
+        // Creating a child keeps the mode of an existing directory
+        let dir = dir.create("override/file", FileType::EmptyFile);
+        assert_eq!(mode(dir.path("override")), 0o700);
+
+        // Only root can create a child in a read-only directory
+        if is_root() {
+            let dir = dir
+                .create("readonly", FileType::DirWithMode(0o555))
+                .create("readonly/file", FileType::EmptyFile);
+            assert_eq!(mode(dir.path("readonly")), 0o555);
+        }
+    }
+
+    #[test]
+    fn test_testdir_cleanup_log() {
+        let sink = Arc::new(Mutex::new(vec![]));
+
+        let dir = TestDir::temp().cleanup_log(Arc::clone(&sink));
+        let deletion_root = dir.deletion_root().unwrap().to_path_buf();
+        assert!(sink.lock().unwrap().is_empty());
+
+        drop(dir);
+        assert_eq!(*sink.lock().unwrap(), vec![deletion_root.clone()]);
+        assert!(!deletion_root.exists());
+
+        // The recorded entry follows a renamed root
+        let new_name = format!("logged_{}", TempDir::random_name(DEFAULT_ENTROPY));
+        let dir = TestDir::temp()
+            .cleanup_log(Arc::clone(&sink))
+            .rename_root(&new_name)
+            .unwrap();
+        let renamed_root = dir.root().to_path_buf();
+        drop(dir);
+        assert_eq!(*sink.lock().unwrap(), vec![deletion_root, renamed_root]);
+    }
+
+    #[test]
+    fn test_testdir_utf8_file() {
+        let dir = TestDir::temp().create("text", FileType::Utf8File("héllo".into()));
+
+        assert_eq!(fs::read_to_string(dir.path("text")).unwrap(), "héllo");
+        assert_eq!(dir.path("text").metadata().unwrap().len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_dir_mode_cleanup() {
+        let path;
+        {
+            let dir = TestDir::temp()
+                .create("filled/file", FileType::EmptyFile)
+                .create("filled", FileType::DirWithMode(0o555))
+                .default_dir_mode(0o500)
+                .create("locked/inner", FileType::Dir);
+            path = dir.root().to_path_buf();
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_testdir_readonly_cleanup() {
+        let path;
+        {
+            let dir = TestDir::temp().create("sub/readonly", FileType::EmptyFile);
+            let mut permissions = dir.path("sub/readonly").metadata().unwrap().permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(dir.path("sub/readonly"), permissions).unwrap();
+            path = dir.root().to_path_buf();
+        }
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_broken_symlink() {
+        let dir = TestDir::temp().create(
+            "dangling",
+            FileType::BrokenSymlink(PathBuf::from("missing")),
+        );
+
+        let path = dir.path("dangling");
+        assert!(path.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(
+            path.metadata().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert!(dir.get_files().contains(&path));
+
+        // The link is relative to its own directory
+        let dir = dir
+            .create(
+                "a/b/link",
+                FileType::BrokenSymlink(PathBuf::from("a/c/missing")),
+            )
+            .create("a/up", FileType::BrokenSymlink(PathBuf::from("x/../other")));
+        assert_eq!(
+            fs::read_link(dir.path("dangling")).unwrap(),
+            PathBuf::from("missing")
+        );
+        assert_eq!(
+            fs::read_link(dir.path("a/b/link")).unwrap(),
+            PathBuf::from("../c/missing")
+        );
+        assert_eq!(
+            fs::read_link(dir.path("a/up")).unwrap(),
+            PathBuf::from("../other")
+        );
+
+        // Escaping or absolute targets are rejected
+        let mut dir = dir;
+        let outside = dir.root().parent().unwrap().join("outside");
+        for target in &[PathBuf::from("../../outside"), outside] {
+            let err = dir
+                .try_create("escape", FileType::BrokenSymlink(target.clone()))
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(dir.path("escape").symlink_metadata().is_err());
+
+        // Identical fixtures do not differ
+        let other = TestDir::temp()
+            .create(
+                "dangling",
+                FileType::BrokenSymlink(PathBuf::from("missing")),
+            )
+            .create(
+                "a/b/link",
+                FileType::BrokenSymlink(PathBuf::from("a/c/missing")),
+            )
+            .create("a/up", FileType::BrokenSymlink(PathBuf::from("x/../other")));
+        assert!(dir.diff_against(other.root()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_remove_broken_symlink() {
+        let mut dir = TestDir::temp()
+            .create(
+                "dangling",
+                FileType::BrokenSymlink(PathBuf::from("missing")),
+            )
+            .create("other", FileType::BrokenSymlink(PathBuf::from("missing2")))
+            .remove("dangling");
+        assert!(dir.path("dangling").symlink_metadata().is_err());
+
+        dir.try_remove("other").unwrap();
+        assert!(dir.path("other").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn test_testdir_walk() {
+        let dir = TestDir::temp()
+            .create("a", FileType::EmptyFile)
+            .create("sub/b", FileType::RandomFile(10))
+            .create("sub/deeper/c", FileType::EmptyFile)
+            .create("empty", FileType::Dir);
+
+        let mut files = 0;
+        let mut dirs = vec![];
+        dir.walk(|path, metadata| {
+            if metadata.is_file() {
+                files += 1;
+            } else if metadata.is_dir() {
+                dirs.push(dir.root_relative(path).unwrap());
+            }
+        });
+        assert_eq!(files, 3);
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("empty"),
+                PathBuf::from("sub"),
+                PathBuf::from("sub/deeper")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_walk_follow_symlinks() {
+        let dir = TestDir::temp()
+            .create("target/file", FileType::EmptyFile)
+            .create("link", FileType::RelativeSymlink(PathBuf::from("target")));
+
+        let mut count = 0;
+        dir.walk(|_, _| count += 1);
+        assert_eq!(count, 3);
+
+        let mut count = 0;
+        dir.walk_with(true, |_, _| count += 1);
+        assert_eq!(count, 4);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_testdir_gzip_file() {
+        let content = b"line 1\nline 2\n".to_vec();
+        let dir = TestDir::temp().create("log.gz", FileType::GzipFile(content.clone()));
+
+        let file = fs::File::open(dir.path("log.gz")).unwrap();
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(file)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, content);
+    }
+
+    #[test]
+    fn test_testdir_deletion_root() {
+        let base = TestDir::temp().create("existing", FileType::Dir);
+
+        // All parents new
+        let dir = TestDir::current_in(base.root(), "new/b/c").unwrap();
+        assert_eq!(dir.deletion_root(), Some(base.path("new").as_path()));
+        drop(dir);
+        assert!(!base.path("new").exists());
+
+        // Some parents existing
+        let dir = TestDir::current_in(base.root(), "existing/b/c").unwrap();
+        assert_eq!(dir.deletion_root(), Some(base.path("existing/b").as_path()));
+        drop(dir);
+        assert!(base.path("existing").exists());
+        assert!(!base.path("existing/b").exists());
+
+        // Parent is the base directory
+        let dir = TestDir::current_in(base.root(), "leaf").unwrap();
+        assert_eq!(dir.deletion_root(), Some(base.path("leaf").as_path()));
+        drop(dir);
+        assert!(base.root().exists());
+        assert!(!base.path("leaf").exists());
+
+        // The directory itself exists, nothing is removed
+        let dir = TestDir::current_in(base.root(), "existing").unwrap();
+        assert_eq!(dir.deletion_root(), None);
+        drop(dir);
+        assert!(base.path("existing").exists());
+    }
+
+    #[test]
+    fn test_testdir_populate() {
+        let dir = TestDir::temp().populate(
+            "logs",
+            vec![
+                ("a.log", FileType::EmptyFile),
+                ("b.log", FileType::ContentFile(b"b".to_vec())),
+                ("old/c.log", FileType::RandomFile(10)),
+            ],
+        );
+
+        assert!(dir.path("logs").is_dir());
+        assert!(dir.path("logs/a.log").is_file());
+        assert_eq!(fs::read(dir.path("logs/b.log")).unwrap(), b"b");
+        assert!(dir.path("logs/old/c.log").is_file());
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_testdir_sha256() {
+        let dir = TestDir::temp().create("abc", FileType::ContentFile(b"abc".to_vec()));
+
+        assert_eq!(
+            dir.sha256("abc").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(dir.sha256("missing").is_err());
+    }
+
+    #[test]
+    fn test_testdir_create_dirs() {
+        let dir = TestDir::temp().create_dirs(&["x", "y/z", "w"]);
+
+        assert!(dir.path("x").is_dir());
+        assert!(dir.path("y/z").is_dir());
+        assert!(dir.path("w").is_dir());
+        assert_eq!(
+            dir.get_dirs(),
+            &vec![dir.path("x"), dir.path("y/z"), dir.path("w")]
+        );
+    }
+
+    #[test]
+    fn test_testdir_relative_paths() {
+        let dir = TestDir::temp()
+            .create("a/b", FileType::Dir)
+            .create("a/b/file", FileType::EmptyFile);
+
+        let dirs = dir.relative_dirs();
+        assert!(dirs.contains(&PathBuf::from("a/b")));
+        assert!(!dirs.contains(&dir.path("a/b")));
+        assert_eq!(dir.relative_files(), vec![PathBuf::from("a/b/file")]);
+    }
+
+    #[cfg(feature = "operation-log")]
+    #[test]
+    fn test_testdir_operations() {
+        let mut dir = TestDir::temp()
+            .create("dir", FileType::Dir)
+            .create("dir/file", FileType::ZeroFile(3))
+            .create("other", FileType::EmptyFile)
+            .touch("dir/../other")
+            .remove("dir/file");
+        // A failed operation is not recorded
+        assert!(dir
+            .try_create("link", FileType::BrokenSymlink(PathBuf::from("other")))
+            .is_err());
+
+        assert_eq!(
+            dir.operations(),
+            &[
+                Operation::Create {
+                    path: PathBuf::from("dir"),
+                    filetype: "Dir".to_string(),
+                },
+                Operation::Create {
+                    path: PathBuf::from("dir/file"),
+                    filetype: "ZeroFile(3)".to_string(),
+                },
+                Operation::Create {
+                    path: PathBuf::from("other"),
+                    filetype: "EmptyFile".to_string(),
+                },
+                Operation::Touch {
+                    path: PathBuf::from("other"),
+                },
+                Operation::Remove {
+                    path: PathBuf::from("dir/file"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_testdir_create_with_options() {
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+
+        let dir = TestDir::temp()
+            .create_with_options("file", &opts, b"content")
+            .unwrap();
+        assert_eq!(fs::read(dir.path("file")).unwrap(), b"content");
+        assert_eq!(dir.get_files(), &vec![dir.path("file")]);
+
+        match dir.create_with_options("file", &opts, b"again") {
+            Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists),
+            Ok(_) => panic!("Second create_new must fail"),
+        }
+    }
+
+    #[test]
+    fn test_testdir_leak_on_panic() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = std::thread::spawn(move || {
+            let dir = TestDir::temp()
+                .leak_on_panic()
+                .create("evidence", FileType::EmptyFile);
+            sender.send(dir.root().to_path_buf()).unwrap();
+            panic!("Test failure");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let path = receiver.recv().unwrap();
+        assert!(path.join("evidence").exists());
+        fs::remove_dir_all(path).unwrap();
+
+        // Without a panic the directory is removed as usual
+        let path = TestDir::temp().leak_on_panic().root().to_path_buf();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_testdir_create_from_file() {
+        let source =
+            TestDir::temp().create("golden.txt", FileType::ContentFile(b"golden".to_vec()));
+
+        let dir = TestDir::temp()
+            .create_from_file("input/renamed.txt", &source.path("golden.txt"))
+            .unwrap();
+        assert_eq!(fs::read(dir.path("input/renamed.txt")).unwrap(), b"golden");
+        assert!(dir.get_files().contains(&dir.path("input/renamed.txt")));
+
+        assert!(TestDir::temp()
+            .create_from_file("file", &source.path("missing"))
+            .is_err());
+
+        // Copying a file onto itself must not empty it, a non-owning view keeps the file on error
+        let path = dir.path("input/renamed.txt");
+        let view = TestDir::from_existing(dir.root(), false).unwrap();
+        let err = match view.create_from_file("input/../input/renamed.txt", &path) {
+            Err(err) => err,
+            Ok(_) => panic!("Copying a file onto itself must fail"),
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&path).unwrap(), b"golden");
+    }
+
+    #[test]
+    fn test_testdir_zero_size() {
+        let dir = TestDir::temp()
+            .create("zero", FileType::ZeroFile(0))
+            .create("random", FileType::RandomFile(0))
+            .create(
+                "pattern",
+                FileType::PatternFile {
+                    pattern: vec![1],
+                    size: 0,
+                },
+            );
+
+        for name in &["zero", "random", "pattern"] {
+            assert!(dir.path(name).is_file());
+            assert_eq!(dir.path(name).metadata().unwrap().len(), 0);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_assert_mode() {
+        let dir = TestDir::temp()
+            .default_file_mode(0o640)
+            .create("file", FileType::EmptyFile);
+
+        dir.assert_mode("file", 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[should_panic(expected = "File \"file\" has mode 0o640, expected 0o600")]
+    fn test_testdir_assert_mode_panic() {
+        let dir = TestDir::temp()
+            .default_file_mode(0o640)
+            .create("file", FileType::EmptyFile);
+
+        dir.assert_mode("file", 0o600);
+    }
+
+    #[test]
+    fn test_testdir_from_existing() {
+        let base = TestDir::temp().create("existing/file", FileType::EmptyFile);
+
+        let dir = TestDir::from_existing(&base.path("existing"), false).unwrap();
+        assert_eq!(dir.root(), base.path("existing"));
+        assert!(dir.path("file").exists());
+        assert_eq!(dir.deletion_root(), None);
+        drop(dir);
+        assert!(base.path("existing/file").exists());
+
+        let dir = TestDir::from_existing(&base.path("existing"), true).unwrap();
+        drop(dir);
+        assert!(!base.path("existing").exists());
+
+        assert!(TestDir::from_existing(&base.path("missing"), false).is_err());
+    }
+
+    #[test]
+    #[ignore = "writes 100 MB, run with --ignored"]
+    fn test_testdir_large_random_file() {
+        let size = 100 * 1024 * 1024;
+
+        let start = std::time::Instant::now();
+        let dir = TestDir::temp().create("bulk", FileType::RandomFile(size));
+        let bulk = start.elapsed();
+        assert_eq!(dir.path("bulk").metadata().unwrap().len(), size);
+
+        // Small chunks from the seeded generator for comparison
+        let start = std::time::Instant::now();
+        let dir = TestDir::builder()
+            .seed(0)
+            .build()
+            .unwrap()
+            .create("chunked", FileType::RandomFile(size));
+        let chunked = start.elapsed();
+        assert_eq!(dir.path("chunked").metadata().unwrap().len(), size);
+
+        eprintln!("bulk: {:?}, chunked: {:?}", bulk, chunked);
+        assert!(bulk < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_testdir_path_escape() {
+        let mut dir = TestDir::temp();
+
+        for path in &["../x", "a/../../x"] {
+            let err = dir.try_create(path, FileType::EmptyFile).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("escapes the root"));
+            assert!(dir.try_remove(path).is_err());
+        }
+        assert!(!dir.root().parent().unwrap().join("x").exists());
+
+        // Paths resolving to the root itself are rejected
+        dir.try_create("keep", FileType::EmptyFile).unwrap();
+        for path in &["a/..", ".", ""] {
+            let err = dir.try_create(path, FileType::EmptyFile).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            let err = dir.try_remove(path).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert!(dir.path("keep").is_file());
+
+        // Stays inside the root
+        dir.try_create("a/../b", FileType::EmptyFile).unwrap();
+        assert!(dir.path("b").is_file());
+        assert_eq!(dir.path("a/../b"), dir.path("b"));
+        assert!(!dir.path("a").exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "Create file")]
+    fn test_testdir_path_escape_panic() {
+        let _ = TestDir::temp().create("../evil", FileType::EmptyFile);
+    }
+
+    #[test]
+    #[should_panic(expected = "escapes the root")]
+    fn test_testdir_path_escape_path_panic() {
+        let _ = TestDir::temp().path("../evil");
+    }
+
+    #[test]
+    fn test_testdir_temp_in_env() {
+        let base = TestDir::temp();
+        let var = "TEST_DIR_TEMP_IN_ENV";
+
+        std::env::set_var(var, base.root());
+        let dir = TestDir::temp_in_env(var).unwrap();
+        assert_eq!(dir.root().parent(), Some(base.root()));
+
+        std::env::set_var(var, "");
+        let dir = TestDir::temp_in_env(var).unwrap();
+        assert!(dir.root().starts_with(std::env::temp_dir()));
+
+        std::env::remove_var(var);
+        let dir = TestDir::temp_in_env(var).unwrap();
+        assert!(dir.root().starts_with(std::env::temp_dir()));
+    }
+
+    #[test]
+    fn test_testdir_line_count() {
+        let dir = TestDir::temp()
+            .create("trailing", FileType::Utf8File("a\nb\nc\n".into()))
+            .create("no_trailing", FileType::Utf8File("a\nb\nc".into()))
+            .create("empty", FileType::EmptyFile);
+
+        assert_eq!(dir.line_count("trailing").unwrap(), 3);
+        assert_eq!(dir.line_count("no_trailing").unwrap(), 3);
+        assert_eq!(dir.line_count("empty").unwrap(), 0);
+        assert!(dir.line_count("missing").is_err());
+
+        dir.assert_utf8("trailing");
+    }
+
+    #[test]
+    #[should_panic(expected = "is not valid UTF-8, invalid byte at 2")]
+    fn test_testdir_assert_utf8_panic() {
+        let dir = TestDir::temp().create("binary", FileType::ContentFile(vec![b'o', b'k', 0xFF]));
+        dir.assert_utf8("binary");
+    }
+
+    #[test]
+    fn test_testdir_repeated_file() {
+        let dir = TestDir::temp().create(
+            "repeated",
+            FileType::RepeatedFile {
+                block: b"abcd".to_vec(),
+                count: 3,
+            },
+        );
+
+        let content = fs::read(dir.path("repeated")).unwrap();
+        assert_eq!(content.len(), 12);
+        assert_eq!(content, b"abcdabcdabcd");
+
+        // Small blocks repeated past a chunk are written whole
+        let count = (CHUNK_SIZE / 3) as usize + 5;
+        let dir = dir.create(
+            "many",
+            FileType::RepeatedFile {
+                block: b"xyz".to_vec(),
+                count,
+            },
+        );
+        let content = fs::read(dir.path("many")).unwrap();
+        assert_eq!(content.len(), count * 3);
+        assert!(content.chunks(3).all(|part| part == b"xyz"));
+    }
+
+    #[test]
+    fn test_testdir_touch() {
+        let dir = TestDir::temp().create("file", FileType::ContentFile(b"content".to_vec()));
+        let before = dir.path("file").metadata().unwrap().modified().unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let dir = dir.touch("file");
+
+        let after = dir.path("file").metadata().unwrap().modified().unwrap();
+        assert!(after > before);
+        assert_eq!(fs::read(dir.path("file")).unwrap(), b"content");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_testdir_touch_readonly() {
+        let dir = TestDir::temp()
+            .default_file_mode(0o444)
+            .create("readonly", FileType::EmptyFile)
+            .create("sub", FileType::Dir);
+        let modified = |path: &str| dir.path(path).metadata().unwrap().modified().unwrap();
+        let before = (modified("readonly"), modified("sub"));
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let dir = dir.touch("readonly").touch("sub");
+
+        let modified = |path: &str| dir.path(path).metadata().unwrap().modified().unwrap();
+        assert!(modified("readonly") > before.0);
+        assert!(modified("sub") > before.1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot touch file")]
+    fn test_testdir_touch_missing() {
+        let _ = TestDir::temp().touch("missing");
+    }
+
+    #[test]
+    fn test_testdir_scoped() {
+        let path = TestDir::scoped(
+            |dir| dir.create("file", FileType::ContentFile(b"scoped".to_vec())),
+            |dir| {
+                assert_eq!(fs::read(dir.path("file")).unwrap(), b"scoped");
+                dir.root().to_path_buf()
+            },
+        );
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_testdir_ensure_parent() {
+        let dir = TestDir::temp()
+            .create("rootfile", FileType::EmptyFile)
+            .create("a/b/deep", FileType::EmptyFile);
+
+        assert_eq!(dir.path("rootfile").parent(), Some(dir.root()));
+        assert!(dir.path("rootfile").is_file());
+        assert!(dir.path("a/b").is_dir());
+        assert!(dir.path("a/b/deep").is_file());
+        assert_eq!(
+            dir.relative_files(),
+            vec![PathBuf::from("rootfile"), PathBuf::from("a/b/deep")]
+        );
+        // The root is not tracked as a created directory
+        assert_eq!(dir.relative_dirs(), vec![PathBuf::from("a/b")]);
+
+        let mut dir = dir.ensure_parent(false);
+        let err = dir
+            .try_create("missing/file", FileType::EmptyFile)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        dir.try_create("a/b/existing_parent", FileType::EmptyFile)
+            .unwrap();
+        let err = dir.try_create("missing/dir", FileType::Dir).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(!dir.path("missing").exists());
+        dir.try_create("a/b/dir", FileType::Dir).unwrap();
+        assert!(dir.path("a/b/dir").is_dir());
+    }
+
+    #[test]
+    fn test_testdir_read_dir_names() {
+        let dir = TestDir::temp()
+            .create("sub/b", FileType::EmptyFile)
+            .create("sub/c/nested", FileType::EmptyFile)
+            .create("sub/a", FileType::EmptyFile)
+            .create("empty", FileType::Dir);
+
+        assert_eq!(dir.read_dir_names("sub").unwrap(), vec!["a", "b", "c"]);
+        assert!(dir.read_dir_names("empty").unwrap().is_empty());
+        assert!(dir.read_dir_names("sub/a").is_err());
+        assert!(dir.read_dir_names("missing").is_err());
+    }
+
+    #[test]
+    fn test_testdir_null_file() {
+        let dir = TestDir::temp()
+            .create("null", FileType::NullFile(10))
+            .create("zero", FileType::ZeroFile(10))
+            .create("empty", FileType::NullFile(0));
+
+        assert_eq!(fs::read(dir.path("null")).unwrap(), vec![0x00; 10]);
+        assert_eq!(fs::read(dir.path("zero")).unwrap(), vec![0x30; 10]);
+        assert_eq!(fs::metadata(dir.path("empty")).unwrap().len(), 0);
+        assert_ne!(FileType::NullFile(10), FileType::ZeroFile(10));
+    }
+
+    #[test]
+    fn test_testdir_validate() {
+        let dir = TestDir::temp()
+            .create("dir/kept", FileType::EmptyFile)
+            .create("dir/removed", FileType::EmptyFile);
+        assert!(dir.validate().is_empty());
+
+        fs::remove_file(dir.path("dir/removed")).unwrap();
+        assert_eq!(dir.validate(), vec![dir.path("dir/removed")]);
+    }
+
+    #[test]
+    fn test_testdir_concat_into() {
+        let dir = TestDir::temp()
+            .create("a", FileType::ContentFile(b"12".to_vec()))
+            .create("b", FileType::ContentFile(b"34".to_vec()))
+            .concat_into("out/c", &["a", "b"]);
+
+        assert_eq!(fs::read(dir.path("out/c")).unwrap(), b"1234");
+        assert!(dir.get_files().contains(&dir.path("out/c")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot concat")]
+    fn test_testdir_concat_into_missing_source() {
+        TestDir::temp()
+            .create("a", FileType::ContentFile(b"12".to_vec()))
+            .concat_into("c", &["a", "missing"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot concat")]
+    fn test_testdir_concat_into_source() {
+        TestDir::temp()
+            .create("a", FileType::ContentFile(b"12".to_vec()))
+            .create("b", FileType::ContentFile(b"34".to_vec()))
+            .concat_into("a", &["./a", "b"]);
+    }
+
+    #[test]
+    fn test_testdir_temp_with_capacity() {
+        let mut dir = TestDir::temp_with_capacity(100, 10);
+        assert!(dir.get_files().capacity() >= 100);
+        assert!(dir.get_dirs().capacity() >= 10);
+
+        for i in 0..10 {
+            dir.try_create(&format!("dir{}", i), FileType::Dir).unwrap();
+        }
+        for i in 0..100 {
+            dir.try_create(&format!("file{}", i), FileType::EmptyFile)
+                .unwrap();
+        }
+        assert_eq!(dir.get_files().len(), 100);
+        assert_eq!(dir.get_dirs().len(), 10);
+    }
+
+    #[test]
+    fn test_testdir_rename_root() {
+        let dir = TestDir::temp().create("dir/file", FileType::ContentFile(b"x".to_vec()));
+        let old_root = dir.root().to_path_buf();
+        let new_name = format!("renamed_{}", TempDir::random_name(DEFAULT_ENTROPY));
+
+        let dir = dir.rename_root(&new_name).unwrap();
+        let new_root = dir.root().to_path_buf();
+        assert_eq!(new_root, old_root.with_file_name(&new_name));
+        assert!(!old_root.exists());
+        assert_eq!(fs::read(dir.path("dir/file")).unwrap(), b"x");
+        assert_eq!(dir.get_files(), &vec![new_root.join("dir/file")]);
+        assert_eq!(dir.deletion_root(), Some(new_root.as_path()));
+
+        drop(dir);
+        assert!(!new_root.exists());
+    }
+
+    #[test]
+    fn test_testdir_rename_root_invalid_name() {
+        for name in &["", "a/b", "..", "."] {
+            let err = TestDir::temp().rename_root(name).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
 }